@@ -1,12 +1,13 @@
 use serde::Deserialize;
 
 /// An enum type that represents user arguments
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 #[serde(untagged)]
 pub enum Argument {
     String(String),
     Int(u64),
     Vec(Vec<Argument>),
+    #[default] // for #[serde(default)]
     None
 }
 
@@ -63,10 +64,3 @@ impl Argument {
         matches!(self, &Argument::None)
     }
 }
-
-// for #[serde(default)]
-impl Default for Argument {
-    fn default() -> Self {
-        Argument::None
-    }
-}
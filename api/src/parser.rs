@@ -91,7 +91,7 @@ pub fn parse_args<'a, T: Deserialize<'a>>(args: &'a [(String, Argument)]) -> Res
     Ok(t)
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = ArgParseError;
 
     fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ArgParseError> {
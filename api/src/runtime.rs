@@ -8,20 +8,64 @@ pub enum RunLevel { Init, Run, Shut }
 
 // TODO: Box<[u8]> causes a lot of allocation and memcpy. Design a structure that can grow on both sides? Ideally components can give hints about how many bytes they are going to add, so we can preallocate at the begining.
 
+/// The sending side of one direction of a stream. The direction ends (like a TCP FIN) when all clones are dropped. The two
+/// directions of a stream are independent: ending one does not end the other.
 pub trait Address: Clone + Send + Sync + 'static {
+    /// fails if the receiver has dropped its mailbox, which means it aborted rather than finished
     #[must_use]
     fn send(&mut self, msg: Box<[u8]>) -> Pin<Box<dyn Future<Output=Result<(), ()>> + Send + '_>>;
 }
 
+/// The receiving side of one direction of a stream.
 pub trait Mailbox: Send + Sync + 'static {
+    /// returns None once the sender ended this direction
     #[allow(clippy::type_complexity)]
     fn recv(&mut self) -> Pin<Box<dyn Future<Output=Option<Box<[u8]>>> + Send + '_>>;
 }
 
+/// The result of setting up a stream, reported by the node that connects to the outside world (e.g. `tcp`) so the nodes
+/// before it can react, e.g. `balance` tries another output on failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Connected,
+    Refused, // the destination actively refused, e.g. TCP RST
+    Unreachable, // no route to the destination
+    TimedOut,
+    Failed // other errors
+}
+
+impl Status {
+    /// classify a connection error
+    pub fn from_io_error(e: &std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::ConnectionRefused => Status::Refused,
+            std::io::ErrorKind::HostUnreachable | std::io::ErrorKind::NetworkUnreachable => Status::Unreachable,
+            std::io::ErrorKind::TimedOut => Status::TimedOut,
+            _ => Status::Failed
+        }
+    }
+}
+
+/// The sending side of a status channel. Only the first report counts. It is passed to the downstream in the metadata
+/// under the key "status_reporter".
+pub trait StatusReporter: Clone + Send + Sync + 'static {
+    fn report(&self, status: Status);
+}
+
+/// The receiving side of a status channel.
+pub trait StatusWatcher: Send + Sync + 'static {
+    /// wait for the status. Returns None if all reporters are dropped without reporting, i.e. the downstream does not
+    /// report its status.
+    #[allow(clippy::type_complexity)]
+    fn wait(&mut self) -> Pin<Box<dyn Future<Output=Option<Status>> + Send + '_>>;
+}
+
 /// A trait that provides runtime functions to components. It is tied to each actor.
 pub trait Runtime: Sync + Send + Sized + 'static {
     type Address: Address;
     type Mailbox: Mailbox;
+    type StatusReporter: StatusReporter;
+    type StatusWatcher: StatusWatcher;
 
     /// spawn an actor of the i-th output
     /// metadata provides information about this stream
@@ -32,6 +76,9 @@ pub trait Runtime: Sync + Send + Sized + 'static {
     /// establish a new channel
     fn channel(&self) -> (Self::Address, Self::Mailbox);
 
+    /// establish a new status channel
+    fn status_channel(&self) -> (Self::StatusReporter, Self::StatusWatcher);
+
     /// spawn a task that runs on the background
     /// no handler is returned. Use channels to get results if necessary.
     fn spawn_task<F: Future + Send + 'static>(&self, task: F) where F::Output: Send;
@@ -62,3 +109,10 @@ pub async fn pass(address: Option<impl Address>, mailbox: Option<impl Mailbox>)
         }
     }
 }
+
+/// report the status of the stream to the upstream, if it is watching
+pub fn report_status<R: Runtime>(metadata: &MetaData, status: Status) {
+    if let Some(reporter) = metadata.get::<R::StatusReporter>("status_reporter") {
+        reporter.report(status)
+    }
+}
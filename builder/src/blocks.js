@@ -89,6 +89,14 @@ export default function load_blocks(Blockly) {
         comp_name: 'aead',
         category: 'Encryption',
         default_arg_names: ['key']
+    }, {
+        name: 'arq_client',
+        comp_name: 'arq',
+        category: 'Endpoints',
+    }, {
+        name: 'arq_server',
+        comp_name: 'arq',
+        category: 'Endpoints',
     }, {
         name: 'auth',
         category: 'Authentication',
@@ -96,6 +104,10 @@ export default function load_blocks(Blockly) {
     }, {
         name: 'balance',
         category: 'Trivia',
+    }, {
+        name: 'capture',
+        category: 'Trivia',
+        default_arg_names: ['path']
     }, {
         name: 'drop',
         category: 'Trivia',
@@ -106,6 +118,35 @@ export default function load_blocks(Blockly) {
     }, {
         name: 'exec',
         category: 'Trivia',
+    }, {
+        name: 'fec_encode',
+        comp_name: 'fec',
+        category: 'Error Correction',
+    }, {
+        name: 'fec_decode',
+        comp_name: 'fec',
+        category: 'Error Correction',
+    }, {
+        name: 'frame_encode',
+        comp_name: 'frame',
+        category: 'Framing',
+    }, {
+        name: 'frame_decode',
+        comp_name: 'frame',
+        category: 'Framing',
+    }, {
+        name: 'inspect',
+        category: 'Trivia',
+    }, {
+        name: 'record',
+        category: 'Trivia',
+        default_arg_names: ['path']
+    }, {
+        name: 'replay',
+        comp_name: 'record',
+        source_only: true,
+        category: 'Trivia',
+        default_arg_names: ['path']
     }, {
         name: 'socks5_server',
         comp_name: 'socks5',
@@ -114,6 +155,46 @@ export default function load_blocks(Blockly) {
         name: 'vmess_client',
         comp_name: 'vmess',
         category: 'Proxying',
+    }, {
+        name: 'mux_client',
+        comp_name: 'mux',
+        category: 'Proxying',
+    }, {
+        name: 'mux_server',
+        comp_name: 'mux',
+        category: 'Proxying',
+    }, {
+        name: 'noise_client',
+        comp_name: 'noise',
+        category: 'Encryption',
+    }, {
+        name: 'noise_server',
+        comp_name: 'noise',
+        category: 'Encryption',
+    }, {
+        name: 'obfs_encode',
+        comp_name: 'obfs',
+        category: 'Obfuscation',
+    }, {
+        name: 'obfs_decode',
+        comp_name: 'obfs',
+        category: 'Obfuscation',
+    }, {
+        name: 'http_obfs_client',
+        comp_name: 'obfs',
+        category: 'Obfuscation',
+    }, {
+        name: 'http_obfs_server',
+        comp_name: 'obfs',
+        category: 'Obfuscation',
+    }, {
+        name: 'tls_obfs_client',
+        comp_name: 'obfs',
+        category: 'Obfuscation',
+    }, {
+        name: 'tls_obfs_server',
+        comp_name: 'obfs',
+        category: 'Obfuscation',
     }, {
         name: 'stdio',
         comp_name: 'stdio',
@@ -149,6 +230,16 @@ export default function load_blocks(Blockly) {
         name: 'xor',
         category: 'Encryption',
         default_arg_names: ['key']
+    }, {
+        name: 'xor_encode',
+        comp_name: 'xor',
+        category: 'Encryption',
+        default_arg_names: ['key']
+    }, {
+        name: 'xor_decode',
+        comp_name: 'xor',
+        category: 'Encryption',
+        default_arg_names: ['key']
     }]
 
     let color_map = Object.create(null)
@@ -0,0 +1,266 @@
+//! The datagram mode, where each message is sealed into a packet of its own:
+//! [salt (16 bytes)][counter (u64)][sealed message with tag]
+//! The salt identifies a session key derived in the same way as the stream mode. The sender picks a new salt when
+//! rekeying but keeps counting, and the receiver keeps the keys and replay windows of a few recent salts. This state
+//! is per stream, so replays are only detected within the same stream.
+
+use ring::aead::{Aad, LessSafeKey, Nonce};
+use ring::rand::SecureRandom;
+
+use super::Actor;
+
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = SALT_LEN + 8;
+const WINDOW: u64 = 1024; // the number of recent counters that the replay window tracks
+const MAX_SESSIONS: usize = 8; // the number of recent salts that the receiver keeps
+
+fn nonce(count: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&count.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// A sliding window over the counters of received packets.
+struct ReplayWindow {
+    top: u64, // one more than the largest accepted counter
+    bitmap: [u64; WINDOW as usize / 64]
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        ReplayWindow { top: 0, bitmap: [0; WINDOW as usize / 64] }
+    }
+
+    /// returns false if the counter is either too old or already accepted
+    fn check(&self, count: u64) -> bool {
+        if count.saturating_add(WINDOW) <= self.top {
+            return false
+        }
+        count >= self.top || self.bitmap[(count % WINDOW / 64) as usize] & (1 << (count % 64)) == 0
+    }
+
+    /// mark the counter as accepted. Must only be called for authenticated packets that passed `check`.
+    fn update(&mut self, count: u64) {
+        if count >= self.top {
+            if count - self.top >= WINDOW {
+                self.bitmap = [0; WINDOW as usize / 64]
+            } else {
+                for x in self.top..count {
+                    self.bitmap[(x % WINDOW / 64) as usize] &= !(1 << (x % 64))
+                }
+            }
+            self.top = count + 1
+        }
+        self.bitmap[(count % WINDOW / 64) as usize] |= 1 << (count % 64)
+    }
+}
+
+struct Session {
+    salt: [u8; SALT_LEN],
+    key: LessSafeKey,
+    window: ReplayWindow
+}
+
+/// The keys and replay windows of the recent salts of a sender. The counter continues across salts, so a salt that
+/// has been evicted can be recognized by its counters being lower than the ones already seen from later salts.
+struct Receiver {
+    sessions: Vec<Session>, // the most recently used at the end
+    floor: u64 // counters below this belong to evicted salts
+}
+
+impl Receiver {
+    fn new() -> Self {
+        Receiver { sessions: vec![], floor: 0 }
+    }
+
+    /// authenticate a packet and returns its content, or None if it is forged or replayed. A new salt only gets a
+    /// session after a packet of it authenticated, so forged packets cannot evict the genuine sessions.
+    fn open<'a>(&mut self, msg: &'a mut [u8], derive: impl FnOnce(&[u8]) -> LessSafeKey) -> Option<&'a [u8]> {
+        let salt: [u8; SALT_LEN] = msg[..SALT_LEN].try_into().unwrap();
+        let count = u64::from_be_bytes(msg[SALT_LEN..HEADER_LEN].try_into().unwrap());
+
+        if let Some(i) = self.sessions.iter().position(|x| x.salt == salt) {
+            let session = &mut self.sessions[i];
+            if !session.window.check(count) {
+                return None
+            }
+            let content = session.key.open_in_place(nonce(count), Aad::empty(), &mut msg[HEADER_LEN..]).ok()?;
+            session.window.update(count);
+            let session = self.sessions.remove(i);
+            self.sessions.push(session);
+            return Some(content)
+        }
+
+        if count < self.floor {
+            return None
+        }
+
+        let key = derive(&salt);
+        let content = key.open_in_place(nonce(count), Aad::empty(), &mut msg[HEADER_LEN..]).ok()?;
+        let mut window = ReplayWindow::new();
+        window.update(count);
+        if self.sessions.len() == MAX_SESSIONS {
+            let evicted = self.sessions.remove(0);
+            self.floor = self.floor.max(evicted.window.top);
+        }
+        self.sessions.push(Session { salt, key, window });
+        Some(content)
+    }
+}
+
+impl Actor {
+    fn datagram_key(&self, salt: &[u8]) -> LessSafeKey {
+        LessSafeKey::new(self.key_schedule(salt).key())
+    }
+
+    /// The counter is never reset, even when the salt changes on rekeying. See `Receiver`.
+    pub(crate) async fn encode_datagram(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut salt = [0; SALT_LEN];
+        let mut key = None;
+        let mut count: u64 = 0;
+        let (mut records, mut bytes) = (0, 0); // since the last rekey
+
+        while let Some(msg) = mail.recv().await {
+            if count == u64::MAX {
+                return eprintln!("aead: nonces exhausted")
+            }
+
+            let rekey = (self.rekey_records != 0 && records >= self.rekey_records) || (self.rekey_bytes != 0 && bytes >= self.rekey_bytes);
+            if key.is_none() || rekey {
+                self.rand.fill(&mut salt).unwrap();
+                key = Some(self.datagram_key(&salt));
+                (records, bytes) = (0, 0);
+            }
+
+            let mut buf = Vec::with_capacity(HEADER_LEN + msg.len() + self.algo.tag_len());
+            buf.extend_from_slice(&salt);
+            buf.extend_from_slice(&count.to_be_bytes());
+            buf.extend_from_slice(&msg);
+            let tag = key.as_ref().unwrap().seal_in_place_separate_tag(nonce(count), Aad::empty(), &mut buf[HEADER_LEN..]).unwrap();
+            buf.extend_from_slice(tag.as_ref());
+
+            count += 1;
+            records += 1;
+            bytes += msg.len() as u64;
+
+            if addr.send(buf.into()).await.is_err() {
+                return
+            }
+        }
+    }
+
+    /// Packets that fail to decrypt or are replayed are dropped silently without closing the stream.
+    pub(crate) async fn decode_datagram(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut receiver = Receiver::new();
+
+        while let Some(mut msg) = mail.recv().await {
+            if msg.len() < HEADER_LEN + self.algo.tag_len() {
+                continue
+            }
+
+            let content = match receiver.open(&mut msg, |salt| self.datagram_key(salt)) {
+                Some(content) => Box::from(content),
+                None => continue
+            };
+
+            if addr.send(content).await.is_err() {
+                return
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::aead::{UnboundKey, CHACHA20_POLY1305};
+
+    use super::*;
+
+    #[test]
+    fn window() {
+        let mut window = ReplayWindow::new();
+        for count in [0, 5, 3, 2000, 1990] {
+            assert!(window.check(count));
+            window.update(count);
+            assert!(!window.check(count));
+        }
+        assert!(window.check(1989));
+        assert!(!window.check(5)); // too old
+        assert!(!window.check(2001 - WINDOW));
+        assert!(window.check(2002 - WINDOW));
+        assert!(window.check(u64::MAX));
+    }
+
+    #[test]
+    fn window_slide() {
+        let mut window = ReplayWindow::new();
+        window.update(10);
+        window.update(10 + WINDOW - 2);
+        assert!(!window.check(10)); // still in the window
+        assert!(window.check(11));
+
+        window.update(10 + WINDOW - 1);
+        assert!(!window.check(10)); // too old
+        assert!(window.check(11));
+
+        // the bit of 10 is reused, and must have been cleared when sliding
+        window.update(10 + WINDOW + 5);
+        assert!(window.check(10 + WINDOW));
+    }
+
+    fn key(salt: &[u8]) -> LessSafeKey {
+        let mut key = [0; 32];
+        key[..SALT_LEN].copy_from_slice(salt);
+        LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap())
+    }
+
+    fn seal(salt: u8, count: u64, content: &[u8]) -> Vec<u8> {
+        let salt = [salt; SALT_LEN];
+        let mut buf = salt.to_vec();
+        buf.extend_from_slice(&count.to_be_bytes());
+        buf.extend_from_slice(content);
+        let tag = key(&salt).seal_in_place_separate_tag(nonce(count), Aad::empty(), &mut buf[HEADER_LEN..]).unwrap();
+        buf.extend_from_slice(tag.as_ref());
+        buf
+    }
+
+    fn open(receiver: &mut Receiver, mut packet: Vec<u8>) -> Option<Vec<u8>> {
+        receiver.open(&mut packet, key).map(|x| x.to_vec())
+    }
+
+    #[test]
+    fn replay() {
+        let mut receiver = Receiver::new();
+        let packet = seal(1, 0, b"hello");
+        assert_eq!(open(&mut receiver, packet.clone()).as_deref(), Some(&b"hello"[..]));
+        assert_eq!(open(&mut receiver, packet), None);
+    }
+
+    #[test]
+    fn forged_salts_do_not_evict() {
+        let mut receiver = Receiver::new();
+        let captured = seal(1, 0, b"hello");
+        assert!(open(&mut receiver, captured.clone()).is_some());
+
+        for salt in 2..2 + MAX_SESSIONS as u8 {
+            let mut forged = seal(salt, 0, b"x");
+            *forged.last_mut().unwrap() ^= 1;
+            assert_eq!(open(&mut receiver, forged), None);
+        }
+        assert_eq!(open(&mut receiver, captured), None);
+    }
+
+    #[test]
+    fn evicted_salts_stay_rejected() {
+        let mut receiver = Receiver::new();
+        let captured = seal(1, 0, b"hello");
+        assert!(open(&mut receiver, captured.clone()).is_some());
+
+        // the sender rekeys many times, and the counter goes on
+        for salt in 2..2 + MAX_SESSIONS as u8 {
+            assert!(open(&mut receiver, seal(salt, salt as u64, b"x")).is_some());
+        }
+        assert!(receiver.sessions.iter().all(|x| x.salt != [1; SALT_LEN]));
+        assert_eq!(open(&mut receiver, captured), None);
+    }
+}
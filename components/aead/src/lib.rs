@@ -1,7 +1,10 @@
 use std::num::NonZeroU32;
+use std::time::Duration;
 
 use api::serde::Deserialize;
-use ring::{aead::{Algorithm, BoundKey, UnboundKey, NonceSequence, Nonce, SealingKey, Aad, OpeningKey}, rand::{SecureRandom, SystemRandom}};
+mod datagram;
+
+use ring::{aead::{Algorithm, BoundKey, UnboundKey, NonceSequence, Nonce, SealingKey, Aad, OpeningKey}, hkdf, rand::{SecureRandom, SystemRandom}};
 
 struct Component {
     rand: SystemRandom
@@ -10,30 +13,71 @@ struct Component {
 #[derive(Clone, Copy)]
 enum Role { Encoder, Decoder }
 
+/// Nonces are plain counters since every session key is used by only one direction of one stream.
+#[derive(Default)]
 struct Counter {
-    iv: [u8; 4],
     count: u64
 }
 
-impl Counter {
-    fn new(iv: [u8; 4]) -> Self {
-        Counter { iv, count: 0 }
-    }
-}
-
 impl NonceSequence for Counter {
     fn advance(&mut self) -> Result<Nonce, ring::error::Unspecified> {
+        if self.count == u64::MAX {
+            return Err(ring::error::Unspecified) // never wrap
+        }
+
         let mut nonce = [0; 12];
-        nonce[..4].copy_from_slice(&self.iv);
         nonce[4..].copy_from_slice(&self.count.to_be_bytes());
         self.count += 1;
         Ok(Nonce::assume_unique_for_key(nonce))
     }
 }
 
+const MAX_RECORD: usize = u16::MAX as usize + 1; // the length of a record is stored as u16 minus 1
+const SALT_LEN: usize = 32;
+
+/// Derives the session keys of a stream from the long-term key and the salt in the header. Both sides count the records
+/// and move to the next key at the same point, so rekeying needs no extra message.
+struct KeySchedule {
+    prk: hkdf::Prk,
+    algo: &'static Algorithm,
+    generation: u64,
+    records: u64,
+    bytes: u64,
+    rekey_records: u64,
+    rekey_bytes: u64
+}
+
+impl KeySchedule {
+    fn key(&self) -> UnboundKey {
+        let generation = self.generation.to_be_bytes();
+        let info = [b"sopipe aead".as_slice(), &generation];
+        self.prk.expand(&info, self.algo).unwrap().into()
+    }
+
+    /// count a record and returns true if the key should be changed after it
+    fn count(&mut self, len: usize) -> bool {
+        self.records += 1;
+        self.bytes += len as u64;
+
+        if (self.rekey_records != 0 && self.records >= self.rekey_records) || (self.rekey_bytes != 0 && self.bytes >= self.rekey_bytes) {
+            self.generation += 1;
+            self.records = 0;
+            self.bytes = 0;
+            return true
+        }
+
+        false
+    }
+}
+
 struct Actor {
     key: Box<[u8]>,
     algo: &'static Algorithm,
+    max_record: usize,
+    coalesce: Duration,
+    rekey_records: u64,
+    rekey_bytes: u64,
+    datagram: bool,
     role: Role,
     rand: &'static SystemRandom
 }
@@ -51,6 +95,15 @@ impl<R: api::Runtime> api::Component<R> for Component {
 
             salt: Option<&'a str>,
 
+            max_record: Option<usize>,
+            coalesce: Option<u64>, // ms
+
+            rekey_records: Option<u64>,
+            rekey_bytes: Option<u64>,
+
+            #[serde(default)]
+            datagram: bool,
+
             outputs: Vec<&'a str>,
             function_name: &'a str,
         }
@@ -70,8 +123,17 @@ impl<R: api::Runtime> api::Component<R> for Component {
         let salt = config.salt.map(|x| x.as_bytes()).unwrap_or(b"sopipe_is_good");
         let key = derive_key(algo, salt, config.key.as_bytes());
 
+        let max_record = config.max_record.unwrap_or(MAX_RECORD);
+        if max_record == 0 || max_record > MAX_RECORD {
+            panic!("aead max_record must be between 1 and {}", MAX_RECORD)
+        }
+
         Box::new(Actor {
-            key, algo,
+            key, algo, max_record,
+            coalesce: Duration::from_millis(config.coalesce.unwrap_or(0)),
+            rekey_records: config.rekey_records.unwrap_or(1 << 20),
+            rekey_bytes: config.rekey_bytes.unwrap_or(1 << 30),
+            datagram: config.datagram,
             role: match config.function_name {
                 "aead_encode" => Role::Encoder,
                 "aead_decode" => Role::Decoder,
@@ -92,10 +154,8 @@ impl<R: api::Runtime> api::Component<R> for Component {
 
 impl<R: api::Runtime> api::Actor<R> for Actor {
     fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
-        if let Some(stream_type) = metadata.get::<String>("stream_type") {
-            if stream_type == "UDP" {
-                eprintln!("WARNING: the aead module is not designed for UDP")
-            }
+        if !self.datagram && metadata.get::<bool>("datagram").copied().unwrap_or(false) {
+            eprintln!("WARNING: use the datagram mode of the aead module for datagrams")
         }
 
         let (forward_address, forward_mailbox) = runtime.channel();
@@ -104,11 +164,15 @@ impl<R: api::Runtime> api::Actor<R> for Actor {
         match self.role {
             Role::Encoder => {
                 runtime.spawn_task(self.encode(forward_address, mailbox.expect("no mailbox")));
-                runtime.spawn_task(self.decode(address.expect("no address"), backward_mailbox));
+                if let Some(address) = address { // datagram sources may not accept replies
+                    runtime.spawn_task(self.decode(address, backward_mailbox));
+                }
             }
             Role::Decoder => {
                 runtime.spawn_task(self.decode(forward_address, mailbox.expect("no mailbox")));
-                runtime.spawn_task(self.encode(address.expect("no address"), backward_mailbox));
+                if let Some(address) = address {
+                    runtime.spawn_task(self.encode(address, backward_mailbox));
+                }
             }
         }
     }
@@ -122,48 +186,96 @@ impl<R: api::Runtime> api::Actor<R> for Actor {
 }
 
 impl Actor {
-    async fn encode(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
-        let mut iv = [0; 4];
-        self.rand.fill(&mut iv).unwrap();
-
-        let mut sealing_key = {
-            let counter = Counter::new(iv);
-            let unbound_key = UnboundKey::new(self.algo, &self.key).unwrap();
-            SealingKey::new(unbound_key, counter)
-        };
+    async fn encode(&self, addr: impl api::Address, mail: impl api::Mailbox) {
+        if self.datagram {
+            self.encode_datagram(addr, mail).await
+        } else {
+            self.encode_stream(addr, mail).await
+        }
+    }
 
-        if addr.send(Box::from(iv)).await.is_err() {
+    async fn decode(&self, addr: impl api::Address, mail: impl api::Mailbox) {
+        if self.datagram {
+            self.decode_datagram(addr, mail).await
+        } else {
+            self.decode_stream(addr, mail).await
+        }
+    }
+
+    async fn encode_stream(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut salt = [0; SALT_LEN];
+        self.rand.fill(&mut salt).unwrap();
+
+        let mut schedule = self.key_schedule(&salt);
+        let mut sealing_key = SealingKey::new(schedule.key(), Counter::default());
+
+        if addr.send(Box::from(salt)).await.is_err() {
             return
         };
 
-        // TODO: buffering?
-        while let Some(mut msg) = mail.recv().await {
-            // every packet is encrypted twice, one for the length and one for the content, without aad
-
-            if msg.is_empty() {
-                continue
+        let mut pending: Vec<u8> = vec![];
+        while let Some(msg) = mail.recv().await {
+            pending.extend_from_slice(&msg);
+
+            // coalesce the messages that arrive within `coalesce`. With a zero duration, only those already queued.
+            let deadline = tokio::time::Instant::now() + self.coalesce;
+            let mut closed = false;
+            while pending.len() < self.max_record {
+                match tokio::time::timeout_at(deadline, mail.recv()).await {
+                    Ok(Some(msg)) => pending.extend_from_slice(&msg),
+                    Ok(None) => { closed = true; break }
+                    Err(_) => break
+                }
             }
 
-            let length_msg = msg.len() - 1;
-            if length_msg > u16::MAX as _ {
-                todo!()
-            }
+            // oversized messages are split into multiple records
+            for chunk in pending.chunks(self.max_record) {
+                let record = match self.seal(&mut sealing_key, chunk) {
+                    Ok(record) => record,
+                    Err(_) => return eprintln!("aead: nonce exhausted")
+                };
 
-            let mut buf = Vec::with_capacity(2 + self.algo.tag_len() + msg.len() + self.algo.tag_len());
-            buf.extend_from_slice(&u16::to_be_bytes(length_msg as _));
-            sealing_key.seal_in_place_append_tag(Aad::empty(), &mut buf).unwrap();
+                if addr.send(record).await.is_err() {
+                    return
+                }
 
-            let tag = sealing_key.seal_in_place_separate_tag(Aad::empty(), &mut msg).unwrap();
-            buf.extend_from_slice(&msg);
-            buf.extend_from_slice(tag.as_ref());
+                if schedule.count(chunk.len()) {
+                    sealing_key = SealingKey::new(schedule.key(), Counter::default())
+                }
+            }
+            pending.clear();
 
-            if addr.send(buf.into_boxed_slice()).await.is_err() {
+            if closed {
                 return
             }
         }
     }
 
-    async fn decode(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+    /// every record is encrypted twice, one for the length and one for the content, without aad
+    fn seal(&self, sealing_key: &mut SealingKey<Counter>, content: &[u8]) -> Result<Box<[u8]>, ring::error::Unspecified> {
+        let tag_len = self.algo.tag_len();
+        let mut buf = Vec::with_capacity(2 + tag_len + content.len() + tag_len);
+        buf.extend_from_slice(&u16::to_be_bytes((content.len() - 1) as _));
+        sealing_key.seal_in_place_append_tag(Aad::empty(), &mut buf)?;
+
+        let offset = buf.len();
+        buf.extend_from_slice(content);
+        let tag = sealing_key.seal_in_place_separate_tag(Aad::empty(), &mut buf[offset..])?;
+        buf.extend_from_slice(tag.as_ref());
+        Ok(buf.into_boxed_slice())
+    }
+
+    fn key_schedule(&self, salt: &[u8]) -> KeySchedule {
+        KeySchedule {
+            prk: hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&self.key),
+            algo: self.algo,
+            generation: 0, records: 0, bytes: 0,
+            rekey_records: self.rekey_records,
+            rekey_bytes: self.rekey_bytes
+        }
+    }
+
+    async fn decode_stream(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
         let mut buf: Vec<u8> = vec![]; // todo: ring buffer (dequeue) for performance
 
         macro_rules! accumulate_buf_until_length {
@@ -172,19 +284,20 @@ impl Actor {
                     if let Some(msg) = mail.recv().await {
                         buf.extend(&*msg)
                     } else {
+                        if !buf.is_empty() {
+                            eprintln!("aead: stream ended in the middle of a record")
+                        }
                         return
                     }
                 }
             }};
         }
 
-        accumulate_buf_until_length!(4);
-        let iv = buf[..4].try_into().unwrap();
-        buf.drain(..4);
+        accumulate_buf_until_length!(SALT_LEN);
+        let mut schedule = self.key_schedule(&buf[..SALT_LEN]);
+        buf.drain(..SALT_LEN);
 
-        let counter = Counter::new(iv);
-        let unbound_key = UnboundKey::new(self.algo, &self.key).unwrap();
-        let mut opening_key = OpeningKey::new(unbound_key, counter);
+        let mut opening_key = OpeningKey::new(schedule.key(), Counter::default());
 
         loop {
             let length_msg_offset = 2 + self.algo.tag_len();
@@ -208,6 +321,10 @@ impl Actor {
             };
 
             buf.drain(..total_offset);
+
+            if schedule.count(length) {
+                opening_key = OpeningKey::new(schedule.key(), Counter::default())
+            }
         }
     }
 }
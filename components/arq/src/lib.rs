@@ -0,0 +1,212 @@
+use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+use std::time::Duration;
+
+use api::serde::Deserialize;
+use tokio::time::Instant;
+
+mod protocol;
+
+struct Component;
+
+#[derive(Clone, Copy)]
+enum Role { Client, Server }
+
+const LINGER: Duration = Duration::from_secs(3); // keep acknowledging the remote FIN for a while after both sides finished
+
+struct Actor {
+    config: protocol::Config,
+    role: Role
+}
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            window: Option<u16>,
+            mtu: Option<usize>,
+            interval: Option<u32>, // ms
+            resend: Option<u32>,
+
+            #[serde(default)]
+            nodelay: bool,
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("arq must have exactly 1 output")
+        }
+
+        let mtu = config.mtu.unwrap_or(1400);
+        if mtu <= protocol::HEADER_LEN {
+            panic!("arq mtu too small")
+        }
+
+        Box::new(Actor {
+            config: protocol::Config {
+                window: config.window.unwrap_or(128),
+                mtu,
+                interval: config.interval.unwrap_or(if config.nodelay { 10 } else { 40 }),
+                nodelay: config.nodelay,
+                resend: config.resend.unwrap_or(2)
+            },
+            role: match config.function_name {
+                "arq_client" => Role::Client,
+                "arq_server" => Role::Server,
+                _ => unreachable!()
+            }
+        })
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["arq_client", "arq_server"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "arq"
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Actor {
+    fn spawn(&'static self, runtime: R, mut metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+
+        match self.role {
+            Role::Client => {
+                // the client turns the stream into datagrams
+                metadata.set("stream_type".into(), "UDP".to_string());
+                metadata.set("datagram".into(), true);
+                runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+                runtime.spawn_task_with_runtime(move |runtime| self.run(runtime, Some(rand::random()),
+                    address.expect("no address"), mailbox.expect("no mailbox"), forward_address, backward_mailbox))
+            }
+            Role::Server => {
+                metadata.set("stream_type".into(), "TCP".to_string());
+                metadata.set("datagram".into(), false);
+                runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+                runtime.spawn_task_with_runtime(move |runtime| self.run(runtime, None,
+                    forward_address, backward_mailbox, address.expect("no address"), mailbox.expect("no mailbox")))
+            }
+        }
+    }
+}
+
+impl Actor {
+    async fn run(
+        &self, runtime: impl api::Runtime, conv: Option<u32>,
+        mut stream_address: impl api::Address + 'static, mut stream_mailbox: impl api::Mailbox,
+        mut datagram_address: impl api::Address + 'static, mut datagram_mailbox: impl api::Mailbox
+    ) {
+        let start = Instant::now();
+        let now = || start.elapsed().as_millis() as u32;
+
+        let mut session = protocol::Session::new(conv, self.config.clone());
+
+        // deliver the stream data in another task so a slow consumer does not block acknowledging and retransmitting.
+        // The number of undelivered segments is subtracted from our window, so the remote won't overwhelm us.
+        let unread = Arc::new(AtomicUsize::new(0));
+        let (deliver, mut delivery) = tokio::sync::mpsc::unbounded_channel::<Box<[u8]>>();
+        let mut deliver = Some(deliver);
+        {
+            let unread = unread.clone();
+            runtime.spawn_task(async move {
+                let mut consumer_alive = true;
+                while let Some(msg) = delivery.recv().await {
+                    // keep draining after the consumer is gone, so the window still opens for the remote to finish
+                    if consumer_alive && stream_address.send(msg).await.is_err() {
+                        consumer_alive = false
+                    }
+                    unread.fetch_sub(1, Ordering::Relaxed);
+                }
+            });
+        }
+
+        // likewise, sending is decoupled so two peers blocking on sending to each other don't deadlock
+        let (outgoing, mut outgoing_rx) = tokio::sync::mpsc::unbounded_channel::<Box<[u8]>>();
+        runtime.spawn_task(async move {
+            while let Some(datagram) = outgoing_rx.recv().await {
+                if datagram_address.send(datagram).await.is_err() {
+                    return
+                }
+            }
+        });
+
+        let mut interval = tokio::time::interval(Duration::from_millis(self.config.interval as _));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        let mut stream_open = true;
+        let mut finished_at: Option<Instant> = None;
+        let mut delivered = vec![];
+
+        loop {
+            let ticked = tokio::select! {
+                msg = stream_mailbox.recv(), if stream_open && session.waiting() < self.config.window as usize => {
+                    match msg {
+                        Some(msg) => session.send(&msg),
+                        None => {
+                            stream_open = false;
+                            session.close()
+                        }
+                    }
+                    false
+                }
+                msg = datagram_mailbox.recv() => {
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => return // the datagram session is closed
+                    };
+
+                    if session.input(now(), &msg, &mut delivered).is_err() {
+                        eprintln!("arq: invalid packet");
+                    }
+
+                    for data in delivered.drain(..) {
+                        unread.fetch_add(1, Ordering::Relaxed);
+                        if let Some(deliver) = &deliver {
+                            let _ = deliver.send(data);
+                        }
+                    }
+
+                    if session.remote_finished() {
+                        deliver = None // let the delivery task end the stream after draining
+                    }
+                    false
+                }
+                _ = interval.tick() => true
+            };
+
+            // in nodelay mode, send data and acks immediately. Otherwise wait for the next tick to pack more segments.
+            if ticked || self.config.nodelay {
+                for datagram in session.flush(now(), unread.load(Ordering::Relaxed)) {
+                    if outgoing.send(datagram).is_err() {
+                        return
+                    }
+                }
+            }
+
+            // the stream cannot be aborted, so the downstream sees a normal end. Make the truncation visible at least.
+            if session.dead() {
+                eprintln!("arq: link is dead, {} segments unacknowledged, received stream {}", session.unacknowledged(),
+                    if session.remote_finished() { "complete" } else { "truncated" });
+                return
+            }
+
+            if session.finished() {
+                let finished_at = *finished_at.get_or_insert_with(Instant::now);
+                if finished_at.elapsed() > LINGER {
+                    return
+                }
+            }
+        }
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
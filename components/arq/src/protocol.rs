@@ -0,0 +1,541 @@
+//! A KCP-like reliable and ordered protocol. This module is a pure state machine: the caller feeds it with stream data,
+//! received datagrams and the current time, and sends out the datagrams it produces.
+
+use std::collections::{BTreeMap, VecDeque};
+
+pub const HEADER_LEN: usize = 21; // conv (u32) + cmd (u8) + wnd (u16) + ts (u32) + sn (u32) + una (u32) + len (u16)
+
+const CMD_PUSH: u8 = 1;
+const CMD_ACK: u8 = 2;
+const CMD_WASK: u8 = 3; // ask for the remote window size
+const CMD_WINS: u8 = 4; // tell the local window size. Also used for keepalive.
+const CMD_FIN: u8 = 5; // the end of the stream. It is sequenced like PUSH.
+
+const RTO_DEFAULT: u32 = 200;
+const RTO_MIN_NODELAY: u32 = 30;
+const RTO_MIN: u32 = 100;
+const RTO_MAX: u32 = 60000;
+const PROBE_INIT: u32 = 7000;
+const PROBE_LIMIT: u32 = 120000;
+const FASTACK_LIMIT: u32 = 5; // stop fast retransmitting a segment after this number of transmissions
+const DEAD_LINK: u32 = 20; // give up after this number of retransmissions of a segment
+const KEEPALIVE: u32 = 1000; // send something if we kept silent for this long, so the UDP session does not expire
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub window: u16, // in segments
+    pub mtu: usize,
+    pub interval: u32, // ms
+    pub nodelay: bool,
+    pub resend: u32 // fast retransmit after this number of later segments are acked. 0 to disable.
+}
+
+struct Segment {
+    cmd: u8,
+    sn: u32,
+    ts: u32,
+    resend_ts: u32,
+    rto: u32,
+    fastack: u32,
+    xmit: u32,
+    data: Vec<u8>
+}
+
+impl Segment {
+    fn new(cmd: u8, data: Vec<u8>) -> Self {
+        Segment { cmd, sn: 0, ts: 0, resend_ts: 0, rto: 0, fastack: 0, xmit: 0, data }
+    }
+}
+
+pub struct Session {
+    config: Config,
+    conv: Option<u32>, // the server learns it from the first packet
+    mss: usize,
+
+    snd_una: u32,
+    snd_nxt: u32,
+    rcv_nxt: u32,
+
+    snd_queue: VecDeque<Segment>,
+    snd_buf: VecDeque<Segment>,
+    rcv_buf: BTreeMap<u32, (u8, Box<[u8]>)>,
+    acklist: Vec<(u32, u32)>, // (sn, ts)
+
+    rmt_wnd: u16,
+    cwnd: u32,
+    ssthresh: u32,
+    cwnd_acc: u32,
+
+    srtt: u32,
+    rttvar: u32,
+    rto: u32,
+
+    probe_wait: u32,
+    ts_probe: u32,
+    ask_send: bool,
+    ask_tell: bool,
+    last_sent: u32,
+    wnd_zero: bool, // we told the remote that our window is zero
+
+    fin_queued: bool,
+    remote_fin: bool,
+    dead: bool
+}
+
+fn time_diff(later: u32, earlier: u32) -> i32 {
+    later.wrapping_sub(earlier) as i32
+}
+
+/// sequence numbers wrap around like timestamps, so they are compared by their distance
+fn seq_diff(later: u32, earlier: u32) -> i32 {
+    later.wrapping_sub(earlier) as i32
+}
+
+/// packs segments into datagrams. The fields shared by all segments in a flush are fixed.
+struct Writer {
+    mtu: usize,
+    conv: u32,
+    wnd: u16,
+    una: u32,
+    current: Vec<u8>,
+    datagrams: Vec<Box<[u8]>>
+}
+
+impl Writer {
+    fn write(&mut self, cmd: u8, ts: u32, sn: u32, data: &[u8]) {
+        if !self.current.is_empty() && self.current.len() + HEADER_LEN + data.len() > self.mtu {
+            self.datagrams.push(std::mem::take(&mut self.current).into())
+        }
+
+        self.current.extend_from_slice(&self.conv.to_be_bytes());
+        self.current.push(cmd);
+        self.current.extend_from_slice(&self.wnd.to_be_bytes());
+        self.current.extend_from_slice(&ts.to_be_bytes());
+        self.current.extend_from_slice(&sn.to_be_bytes());
+        self.current.extend_from_slice(&self.una.to_be_bytes());
+        self.current.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        self.current.extend_from_slice(data);
+    }
+
+    fn finish(mut self) -> Vec<Box<[u8]>> {
+        if !self.current.is_empty() {
+            self.datagrams.push(self.current.into())
+        }
+        self.datagrams
+    }
+}
+
+impl Session {
+    pub fn new(conv: Option<u32>, config: Config) -> Self {
+        Session {
+            conv,
+            mss: config.mtu - HEADER_LEN,
+            snd_una: 0, snd_nxt: 0, rcv_nxt: 0,
+            snd_queue: Default::default(),
+            snd_buf: Default::default(),
+            rcv_buf: Default::default(),
+            acklist: vec![],
+            rmt_wnd: config.window,
+            cwnd: 1,
+            ssthresh: 2,
+            cwnd_acc: 0,
+            srtt: 0, rttvar: 0, rto: RTO_DEFAULT,
+            probe_wait: 0, ts_probe: 0, ask_send: false, ask_tell: false, wnd_zero: false,
+            last_sent: 0,
+            fin_queued: false, remote_fin: false, dead: false,
+            config
+        }
+    }
+
+    /// queue stream data for sending. Small writes are merged into the last segment.
+    pub fn send(&mut self, mut data: &[u8]) {
+        assert!(!self.fin_queued);
+
+        if let Some(last) = self.snd_queue.back_mut() {
+            let n = data.len().min(self.mss - last.data.len());
+            last.data.extend_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+
+        for chunk in data.chunks(self.mss) {
+            self.snd_queue.push_back(Segment::new(CMD_PUSH, chunk.to_vec()))
+        }
+    }
+
+    /// queue the end of the stream
+    pub fn close(&mut self) {
+        self.fin_queued = true;
+        self.snd_queue.push_back(Segment::new(CMD_FIN, vec![]))
+    }
+
+    /// the number of segments waiting to be sent
+    pub fn waiting(&self) -> usize {
+        self.snd_queue.len()
+    }
+
+    /// whether the remote has ended its stream and all data before that have been delivered
+    pub fn remote_finished(&self) -> bool {
+        self.remote_fin
+    }
+
+    /// whether both directions have ended and all our data are acknowledged
+    pub fn finished(&self) -> bool {
+        self.remote_fin && self.fin_queued && self.snd_queue.is_empty() && self.snd_buf.is_empty()
+    }
+
+    /// whether a segment has been retransmitted too many times
+    pub fn dead(&self) -> bool {
+        self.dead
+    }
+
+    /// the number of segments (including the FIN) not yet acknowledged by the remote
+    pub fn unacknowledged(&self) -> usize {
+        self.snd_queue.len() + self.snd_buf.len()
+    }
+
+    /// process a received datagram. In-order stream data are appended to `delivered`.
+    pub fn input(&mut self, current: u32, mut datagram: &[u8], delivered: &mut Vec<Box<[u8]>>) -> Result<(), ()> {
+        let prev_una = self.snd_una;
+        let mut max_ack: Option<(u32, u32)> = None; // (sn, ts)
+
+        while !datagram.is_empty() {
+            if datagram.len() < HEADER_LEN {
+                return Err(())
+            }
+
+            let conv = u32::from_be_bytes(datagram[0..4].try_into().unwrap());
+            let cmd = datagram[4];
+            let wnd = u16::from_be_bytes(datagram[5..7].try_into().unwrap());
+            let ts = u32::from_be_bytes(datagram[7..11].try_into().unwrap());
+            let sn = u32::from_be_bytes(datagram[11..15].try_into().unwrap());
+            let una = u32::from_be_bytes(datagram[15..19].try_into().unwrap());
+            let len = u16::from_be_bytes(datagram[19..21].try_into().unwrap()) as usize;
+
+            if datagram.len() < HEADER_LEN + len {
+                return Err(())
+            }
+            let data = &datagram[HEADER_LEN..HEADER_LEN + len];
+            datagram = &datagram[HEADER_LEN + len..];
+
+            match self.conv {
+                Some(x) if x != conv => return Err(()),
+                Some(_) => {},
+                None => self.conv = Some(conv)
+            }
+
+            self.rmt_wnd = wnd;
+
+            // cumulative ack
+            while self.snd_buf.front().is_some_and(|x| seq_diff(x.sn, una) < 0) {
+                self.snd_buf.pop_front();
+            }
+            self.snd_una = self.snd_buf.front().map(|x| x.sn).unwrap_or(self.snd_nxt);
+
+            match cmd {
+                CMD_ACK => {
+                    if time_diff(current, ts) >= 0 {
+                        self.update_rtt(time_diff(current, ts) as u32)
+                    }
+                    if let Some(i) = self.snd_buf.iter().position(|x| x.sn == sn) {
+                        self.snd_buf.remove(i);
+                    }
+                    self.snd_una = self.snd_buf.front().map(|x| x.sn).unwrap_or(self.snd_nxt);
+                    if max_ack.is_none_or(|(x, _)| seq_diff(sn, x) > 0) {
+                        max_ack = Some((sn, ts))
+                    }
+                }
+                CMD_PUSH | CMD_FIN => {
+                    if seq_diff(sn, self.rcv_nxt) < self.config.window as i32 {
+                        self.acklist.push((sn, ts));
+                        if seq_diff(sn, self.rcv_nxt) >= 0 {
+                            self.rcv_buf.entry(sn).or_insert_with(|| (cmd, data.into()));
+                        }
+                    }
+
+                    // the buffer is keyed by the raw sequence number, which is not in order across a wrap
+                    while let Some((cmd, data)) = self.rcv_buf.remove(&self.rcv_nxt) {
+                        self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                        if cmd == CMD_FIN {
+                            self.remote_fin = true
+                        } else if !self.remote_fin {
+                            delivered.push(data)
+                        }
+                    }
+                }
+                CMD_WASK => self.ask_tell = true,
+                CMD_WINS => {},
+                _ => return Err(())
+            }
+        }
+
+        // only count acks of segments sent after the last transmission of this one, so a retransmitted segment is
+        // not resent again by the acks that were already on the way
+        if let Some((max_ack, ts)) = max_ack {
+            for segment in self.snd_buf.iter_mut() {
+                if seq_diff(segment.sn, max_ack) < 0 && time_diff(ts, segment.ts) >= 0 {
+                    segment.fastack += 1
+                }
+            }
+        }
+
+        // congestion control: slow start and congestion avoidance
+        if !self.config.nodelay && seq_diff(self.snd_una, prev_una) > 0 && self.cwnd < self.rmt_wnd as u32 {
+            if self.cwnd < self.ssthresh {
+                self.cwnd += 1
+            } else {
+                self.cwnd_acc += 1;
+                if self.cwnd_acc >= self.cwnd {
+                    self.cwnd_acc = 0;
+                    self.cwnd += 1
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update_rtt(&mut self, rtt: u32) {
+        let rtt = rtt.min(RTO_MAX); // the timestamp is echoed by the remote and may be bogus
+        if self.srtt == 0 {
+            self.srtt = rtt;
+            self.rttvar = rtt / 2;
+        } else {
+            let delta = rtt.abs_diff(self.srtt);
+            self.rttvar = (3 * self.rttvar + delta) / 4;
+            self.srtt = ((7 * self.srtt + rtt) / 8).max(1);
+        }
+
+        let rto_min = if self.config.nodelay { RTO_MIN_NODELAY } else { RTO_MIN };
+        self.rto = (self.srtt + self.config.interval.max(4 * self.rttvar)).clamp(rto_min, RTO_MAX);
+    }
+
+    /// produce the datagrams to send. `unread` is the number of delivered segments not yet consumed by the stream.
+    pub fn flush(&mut self, current: u32, unread: usize) -> Vec<Box<[u8]>> {
+        let conv = match self.conv {
+            Some(x) => x,
+            None => return vec![] // we know nothing about the remote yet
+        };
+
+        let wnd = (self.config.window as usize).saturating_sub(self.rcv_buf.len() + unread) as u16;
+        let mut writer = Writer { mtu: self.config.mtu, conv, wnd, una: self.rcv_nxt, current: vec![], datagrams: vec![] };
+
+        for (sn, ts) in std::mem::take(&mut self.acklist) {
+            writer.write(CMD_ACK, ts, sn, &[])
+        }
+
+        // probe the remote window if it is zero
+        if self.rmt_wnd == 0 {
+            if self.probe_wait == 0 {
+                self.probe_wait = PROBE_INIT;
+                self.ts_probe = current.wrapping_add(self.probe_wait);
+            } else if time_diff(current, self.ts_probe) >= 0 {
+                self.probe_wait = (self.probe_wait + self.probe_wait / 2).min(PROBE_LIMIT);
+                self.ts_probe = current.wrapping_add(self.probe_wait);
+                self.ask_send = true;
+            }
+        } else {
+            self.probe_wait = 0;
+        }
+
+        if std::mem::take(&mut self.ask_send) {
+            writer.write(CMD_WASK, current, 0, &[])
+        }
+        // tell the remote as soon as our window reopens, instead of waiting for its probe
+        if self.wnd_zero && wnd > 0 {
+            self.ask_tell = true
+        }
+
+        if std::mem::take(&mut self.ask_tell) {
+            writer.write(CMD_WINS, current, 0, &[])
+        }
+
+        let mut cwnd = (self.config.window as u32).min(self.rmt_wnd as u32);
+        if !self.config.nodelay {
+            cwnd = cwnd.min(self.cwnd)
+        }
+
+        while seq_diff(self.snd_nxt, self.snd_una) < cwnd as i32 {
+            let mut segment = match self.snd_queue.pop_front() {
+                Some(x) => x,
+                None => break
+            };
+            segment.sn = self.snd_nxt;
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.snd_buf.push_back(segment);
+        }
+
+        let rto_min = if self.config.nodelay { 0 } else { self.rto >> 3 };
+        let mut lost = false;
+        let mut fast_resent = false;
+
+        for segment in self.snd_buf.iter_mut() {
+            let need_send = if segment.xmit == 0 {
+                segment.rto = self.rto;
+                segment.resend_ts = current.wrapping_add(segment.rto + rto_min);
+                true
+            } else if time_diff(current, segment.resend_ts) >= 0 {
+                lost = true;
+                segment.rto += if self.config.nodelay { segment.rto / 2 } else { segment.rto.max(self.rto) };
+                segment.rto = segment.rto.min(RTO_MAX);
+                segment.resend_ts = current.wrapping_add(segment.rto);
+                true
+            } else if self.config.resend > 0 && segment.fastack >= self.config.resend && segment.xmit <= FASTACK_LIMIT {
+                fast_resent = true;
+                segment.fastack = 0;
+                segment.resend_ts = current.wrapping_add(segment.rto);
+                true
+            } else {
+                false
+            };
+
+            if need_send {
+                segment.xmit += 1;
+                segment.ts = current;
+                writer.write(segment.cmd, segment.ts, segment.sn, &segment.data);
+                if segment.xmit >= DEAD_LINK {
+                    self.dead = true
+                }
+            }
+        }
+
+        if !self.config.nodelay {
+            let inflight = self.snd_nxt.wrapping_sub(self.snd_una);
+            if fast_resent {
+                self.ssthresh = (inflight / 2).max(2);
+                self.cwnd = self.ssthresh + self.config.resend;
+            }
+            if lost {
+                self.ssthresh = (cwnd / 2).max(2);
+                self.cwnd = 1;
+            }
+        }
+
+        if writer.current.is_empty() && writer.datagrams.is_empty() && time_diff(current, self.last_sent) >= KEEPALIVE as i32 {
+            writer.write(CMD_WINS, current, 0, &[])
+        }
+
+        let datagrams = writer.finish();
+        if !datagrams.is_empty() {
+            self.last_sent = current;
+            self.wnd_zero = wnd == 0;
+        }
+        datagrams
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config { window: 32, mtu: 100, interval: 10, nodelay: true, resend: 2 }
+    }
+
+    /// a client and a server that already exchanged the conv
+    fn pair() -> (Session, Session) {
+        let mut client = Session::new(Some(1), config());
+        let mut server = Session::new(None, config());
+        for datagram in client.flush(0, 0) { // keepalive
+            server.input(0, &datagram, &mut vec![]).unwrap()
+        }
+        (client, server)
+    }
+
+    /// deliver all datagrams from one side to the other, returning the delivered stream data
+    fn transfer(from: &mut Session, to: &mut Session, current: u32) -> Vec<u8> {
+        let mut delivered = vec![];
+        for datagram in from.flush(current, 0) {
+            to.input(current, &datagram, &mut delivered).unwrap()
+        }
+        delivered.concat()
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|x| x as u8).collect()
+    }
+
+    #[test]
+    fn loss_and_retransmit() {
+        let (mut client, mut server) = pair();
+        client.send(b"hello");
+        assert!(!client.flush(0, 0).is_empty()); // lost
+
+        assert!(client.flush(100, 0).is_empty()); // not timed out yet
+        assert_eq!(transfer(&mut client, &mut server, RTO_DEFAULT), b"hello");
+        transfer(&mut server, &mut client, RTO_DEFAULT);
+        assert_eq!(client.unacknowledged(), 0);
+    }
+
+    #[test]
+    fn fast_retransmit() {
+        let (mut client, mut server) = pair();
+        let mss = config().mtu - HEADER_LEN;
+        client.send(&data(mss * 3));
+        let datagrams = client.flush(0, 0);
+        assert_eq!(datagrams.len(), 3);
+
+        // the first one is lost, and the later ones are acked separately
+        let mut delivered = vec![];
+        for datagram in &datagrams[1..] {
+            server.input(0, datagram, &mut delivered).unwrap();
+            transfer(&mut server, &mut client, 1);
+        }
+        assert!(delivered.is_empty());
+
+        // resent long before the RTO
+        assert_eq!(transfer(&mut client, &mut server, 2), data(mss * 3));
+    }
+
+    #[test]
+    fn reordering() {
+        let (mut client, mut server) = pair();
+        let mss = config().mtu - HEADER_LEN;
+        client.send(&data(mss * 4));
+
+        let mut delivered = vec![];
+        for datagram in client.flush(0, 0).iter().rev() {
+            server.input(0, datagram, &mut delivered).unwrap()
+        }
+        assert_eq!(delivered.concat(), data(mss * 4));
+    }
+
+    #[test]
+    fn sequence_wrap() {
+        let (mut client, mut server) = pair();
+        client.snd_una = u32::MAX - 5;
+        client.snd_nxt = u32::MAX - 5;
+        server.rcv_nxt = u32::MAX - 5;
+
+        let mss = config().mtu - HEADER_LEN;
+        let mut received = vec![];
+        for round in 0..4 {
+            client.send(&data(mss * 10));
+            let mut delivered = vec![];
+            // reversed, so the segments after the wrap are buffered before the ones before it
+            for datagram in client.flush(round, 0).iter().rev() {
+                server.input(round, datagram, &mut delivered).unwrap()
+            }
+            received.extend(delivered.concat());
+            transfer(&mut server, &mut client, round);
+            assert_eq!(client.unacknowledged(), 0);
+        }
+
+        assert_eq!(received, data(mss * 10).repeat(4));
+        assert_eq!(client.snd_nxt, 34);
+        assert_eq!(server.rcv_nxt, 34);
+    }
+
+    #[test]
+    fn finish() {
+        let (mut client, mut server) = pair();
+        client.send(b"bye");
+        client.close();
+        server.close();
+        assert_eq!(transfer(&mut client, &mut server, 0), b"bye");
+        assert!(server.remote_finished());
+        transfer(&mut server, &mut client, 0);
+        transfer(&mut client, &mut server, 0);
+        assert!(client.finished());
+        assert!(server.finished());
+    }
+}
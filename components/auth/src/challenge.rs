@@ -3,7 +3,7 @@ use ring::rand::SecureRandom;
 
 // challenge-based authentication. The server send a random nounce (challenge) and the client must reply with the coresponding MAC code.
 
-use crate::ALGORITHM;
+use crate::{ALGORITHM, User, Failure, identify, log};
 
 const NOUNCE_LEN: usize = 20;
 
@@ -55,18 +55,19 @@ impl<R: api::Runtime> api::Actor<R> for Client {
 }
 
 pub struct Server {
-    key: ring::hmac::Key,
+    users: Vec<User>,
+    failure: Failure,
     rand: ring::rand::SystemRandom
 }
 
 impl Server {
-    pub fn new(key: ring::hmac::Key) -> Self {
-        Self { key, rand: ring::rand::SystemRandom::new() }
+    pub fn new(users: Vec<User>, failure: Failure) -> Self {
+        Self { users, failure, rand: ring::rand::SystemRandom::new() }
     }
 }
 
 impl<R: api::Runtime> api::Actor<R> for Server {
-    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+    fn spawn(&'static self, runtime: R, mut metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
         let mut mailbox = mailbox.unwrap();
         let mut address = address.unwrap();
 
@@ -79,26 +80,22 @@ impl<R: api::Runtime> api::Actor<R> for Server {
 
             let mut buf = vec![];
 
-            while buf.len() < ALGORITHM.digest_algorithm().output_len {
-                if let Some(msg) = mailbox.recv().await {
-                    buf.extend_from_slice(&msg);
-                } else {
-                    return
-                }
+            if let Err(event) = self.failure.read(&mut mailbox, &mut buf, ALGORITHM.digest_algorithm().output_len).await {
+                log(&metadata, event, None);
+                return self.failure.handle(runtime, metadata, Some(address), mailbox, &buf).await
             }
 
             let (mac, rest) = buf.split_at(ALGORITHM.digest_algorithm().output_len);
-            if ring::hmac::verify(&self.key, &nounce, mac).is_err() {
-                if let Some(origin) = metadata.get::<std::net::SocketAddr>("origin_addr") {
-                    eprintln!("auth: failed attempt from {}", origin)
-                } else {
-                    eprintln!("auth: failed attempt")
+            match identify(&self.users, &nounce, mac) {
+                Some(user) => user.accept(&mut metadata),
+                None => {
+                    log(&metadata, "failed attempt", None);
+                    return self.failure.handle(runtime, metadata, Some(address), mailbox, &buf).await
                 }
-                return // TODO: cut connection
             }
 
             let (mut address_next, mailbox_next) = runtime.channel();
-            runtime.spawn_next(0, metadata, address, mailbox_next);
+            runtime.spawn_next(self.failure.main, metadata, address, mailbox_next);
 
             #[allow(clippy::collapsible_if)]
             if !rest.is_empty() {
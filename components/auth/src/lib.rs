@@ -1,6 +1,9 @@
 use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use api::serde::Deserialize;
+use ring::rand::SecureRandom;
 
 struct Component;
 
@@ -15,38 +18,85 @@ impl<R: api::Runtime> api::Component<R> for Component {
         #[derive(Debug, Deserialize)]
         #[serde(crate="api::serde")]
         struct Config<'a> {
-            key: &'a str,
+            key: Option<&'a str>,
+            users: Option<&'a str>, // comma-separated name:key pairs
+            users_file: Option<&'a str>,
 
             #[serde(default)]
             method: String,
 
             salt: Option<&'a str>,
 
+            skew: Option<u64>, // ms
+
+            #[serde(default)]
+            on_failure: String,
+            delay: Option<u64>, // ms
+            timeout: Option<u64>, // ms
+
             outputs: Vec<&'a str>,
             function_name: &'a str,
         }
 
         let config: Config = api::parse_args(&arguments).unwrap();
 
-        if config.outputs.len() != 1 {
-            panic!("auth must have exactly 1 output")
-        }
-
         // is it really necessary given that the plain text sits in the argv?
         let salt = config.salt.map(|x| x.as_bytes()).unwrap_or(b"sopipe_is_good");
-        let key = derive_key(salt, config.key.as_bytes());
 
-        match &config.method[..] {
-            "time" => match config.function_name {
-                "auth_client" => Box::new(time::Client::new(key)),
-                "auth_server" => Box::new(time::Server::new(key)),
-                _ => unreachable!()
+        if config.function_name == "auth_client" {
+            if config.outputs.len() != 1 {
+                panic!("auth_client must have exactly 1 output")
+            }
+            if config.users.is_some() || config.users_file.is_some() {
+                panic!("auth_client takes a single key")
+            }
+            let key = derive_key(salt, config.key.expect("auth_client requires key").as_bytes());
+            return match &config.method[..] {
+                "time" => Box::new(time::Client::new(key)),
+                "" | "challenge" => Box::new(challenge::Client::new(key)),
+                _ => panic!("unkown auth method. Avaliable: time, challenge")
+            }
+        }
+
+        let mut users = vec![];
+        if let Some(key) = config.key {
+            users.push(User::new(None, derive_key(salt, key.as_bytes())))
+        }
+        if let Some(list) = config.users {
+            for entry in list.split(',') {
+                users.push(parse_user(entry, salt))
+            }
+        }
+        if let Some(path) = config.users_file {
+            let content = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("auth: cannot read {}: {}", path, e));
+            for line in content.lines().map(|x| x.trim()).filter(|x| !x.is_empty() && !x.starts_with('#')) {
+                users.push(parse_user(line, salt))
             }
-            "" | "challenge" => match config.function_name {
-                "auth_client" => Box::new(challenge::Client::new(key)),
-                "auth_server" => Box::new(challenge::Server::new(key)),
-                _ => unreachable!()
+        }
+        if users.is_empty() {
+            panic!("auth_server requires key, users, or users_file")
+        }
+
+        let fallback = config.outputs.iter().position(|&x| x == "fallback");
+        if config.outputs.len() != 1 + fallback.is_some() as usize {
+            panic!("auth_server must have exactly 1 output besides the optional fallback output")
+        }
+        let failure = Failure {
+            main: if fallback == Some(0) { 1 } else { 0 },
+            policy: match (&config.on_failure[..], fallback) {
+                ("close", _) | ("", None) => Policy::Close,
+                ("delay", _) => Policy::Delay(config.delay.unwrap_or(10000)),
+                ("fallback", Some(i)) | ("", Some(i)) => Policy::Fallback(i),
+                ("fallback", None) => panic!("auth_server requires a fallback output for on_failure=\"fallback\""),
+                _ => panic!("unknown on_failure policy. Available: close, delay, fallback")
             },
+            timeout: Duration::from_millis(config.timeout.unwrap_or(5000)),
+            rand: ring::rand::SystemRandom::new()
+        };
+
+        match &config.method[..] {
+            "time" => Box::new(time::Server::new(users, config.skew.unwrap_or(5000) * 1000, failure)),
+            "" | "challenge" => Box::new(challenge::Server::new(users, failure)),
             _ => panic!("unkown auth method. Avaliable: time, challenge")
         }
     }
@@ -60,6 +110,108 @@ impl<R: api::Runtime> api::Component<R> for Component {
     }
 }
 
+/// A key accepted by the server. The name is put into the metadata as `auth_user` once authenticated.
+pub struct User {
+    name: Option<String>,
+    key: ring::hmac::Key,
+    replay: Mutex<time::ReplayCache> // for the time method
+}
+
+impl User {
+    fn new(name: Option<String>, key: ring::hmac::Key) -> Self {
+        User { name, key, replay: Default::default() }
+    }
+
+    /// set the metadata and log the success
+    fn accept(&self, metadata: &mut api::MetaData) {
+        if let Some(name) = &self.name {
+            metadata.set("auth_user".into(), name.clone());
+        }
+        log(metadata, "authenticated", Some(self))
+    }
+}
+
+impl std::fmt::Display for User {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("(unnamed)"))
+    }
+}
+
+enum Policy {
+    Close,
+    Delay(u64), // close after a random time up to this (ms), while discarding whatever received
+    Fallback(usize) // forward the stream to this output
+}
+
+/// What the server does with a stream that fails the authentication.
+pub struct Failure {
+    main: usize, // the index of the normal output
+    policy: Policy,
+    timeout: Duration, // for receiving the attempt. Probes that send a little and wait are treated as failures.
+    rand: ring::rand::SystemRandom
+}
+
+impl Failure {
+    /// read until `buf` has at least `len` bytes. Returns the event to log if the stream ended or timed out first.
+    async fn read<M: api::Mailbox>(&self, mailbox: &mut M, buf: &mut Vec<u8>, len: usize) -> Result<(), &'static str> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        while buf.len() < len {
+            match tokio::time::timeout_at(deadline, mailbox.recv()).await {
+                Ok(Some(msg)) => buf.extend_from_slice(&msg),
+                Ok(None) => return Err("incomplete attempt"),
+                Err(_) => return Err("timed out attempt")
+            }
+        }
+        Ok(())
+    }
+
+    /// `received` is the data that already read from the mailbox
+    async fn handle<R: api::Runtime>(&self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mut mailbox: R::Mailbox, received: &[u8]) {
+        use api::{Address, Mailbox};
+
+        match self.policy {
+            Policy::Close => {}
+            Policy::Delay(max) => {
+                let mut rand = [0; 8];
+                self.rand.fill(&mut rand).unwrap();
+                let delay = Duration::from_millis(u64::from_be_bytes(rand) % (max + 1));
+                let _ = tokio::time::timeout(delay, async { while mailbox.recv().await.is_some() {} }).await;
+            }
+            Policy::Fallback(index) => {
+                let (mut address_next, mailbox_next) = runtime.channel();
+                runtime.spawn_next(index, metadata, address, mailbox_next);
+
+                if !received.is_empty() && address_next.send(Box::from(received)).await.is_err() {
+                    return
+                }
+
+                api::pass(Some(address_next), Some(mailbox)).await
+            }
+        }
+    }
+}
+
+/// find the user whose key produces the MAC
+fn identify<'a>(users: &'a [User], data: &[u8], mac: &[u8]) -> Option<&'a User> {
+    users.iter().find(|user| ring::hmac::verify(&user.key, data, mac).is_ok())
+}
+
+fn log(metadata: &api::MetaData, event: &str, user: Option<&User>) {
+    match (user, metadata.get::<std::net::SocketAddr>("origin_addr")) {
+        (Some(user), Some(origin)) => eprintln!("auth: {} user {} from {}", event, user, origin),
+        (Some(user), None) => eprintln!("auth: {} user {}", event, user),
+        (None, Some(origin)) => eprintln!("auth: {} from {}", event, origin),
+        (None, None) => eprintln!("auth: {}", event)
+    }
+}
+
+fn parse_user(entry: &str, salt: &[u8]) -> User {
+    match entry.trim().split_once(':') {
+        Some((name, key)) if !name.is_empty() => User::new(Some(name.to_string()), derive_key(salt, key.as_bytes())),
+        _ => panic!("auth users must be in the form of name:key")
+    }
+}
+
 fn derive_key(salt: &[u8], pass: &[u8]) -> ring::hmac::Key {
     let mut key = vec![0; ALGORITHM.digest_algorithm().output_len];
     ring::pbkdf2::derive(ring::pbkdf2::PBKDF2_HMAC_SHA256, NonZeroU32::new(4096).unwrap(), salt, pass, &mut key);
@@ -1,18 +1,20 @@
-//! time-based authentication. The client send the current time (must be monotonic and unique) together with MAC code for verification
+//! time-based authentication. The client send the current time and a random nonce together with MAC code for verification. The server remembers the recent ones of each user to reject replays.
 
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::BTreeSet;
 
-use api::{Address, Mailbox};
+use api::Address;
+use ring::rand::SecureRandom;
 
-use crate::ALGORITHM;
+use crate::{ALGORITHM, User, Failure, identify, log};
 
 pub struct Client {
-    key: ring::hmac::Key
+    key: ring::hmac::Key,
+    rand: ring::rand::SystemRandom
 }
 
 impl Client {
     pub fn new(key: ring::hmac::Key) -> Self {
-        Self { key }
+        Self { key, rand: ring::rand::SystemRandom::new() }
     }
 }
 
@@ -22,7 +24,11 @@ impl<R: api::Runtime> api::Actor<R> for Client {
         runtime.spawn_next(0, metadata, address, mailbox_next);
         runtime.spawn_task(async move {
             let current_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros() as u64; // TODO: nanosecond is still in range of u64 (around 0.35 to the max). Use that instead?
+            let mut nonce = [0; NONCE_LEN];
+            self.rand.fill(&mut nonce).unwrap();
+
             let mut msg = current_time.to_be_bytes().to_vec();
+            msg.extend_from_slice(&nonce);
             let mac = ring::hmac::sign(&self.key, &msg);
             msg.extend_from_slice(mac.as_ref());
             if address_next.send(msg.into()).await.is_err() {
@@ -38,67 +44,92 @@ impl<R: api::Runtime> api::Actor<R> for Client {
     }
 }
 
-// Problem: if some users who have the key purposely adjust their clocks to be slightly faster and keep making connections, they can block other users because this would sets LAST_TIME to be earlier than actual clock.
-static LAST_TIME: AtomicU64 = AtomicU64::new(0); // this is shared across all streams because they all share the same key.
+const NONCE_LEN: usize = 8;
+const CACHE_SIZE: usize = 65536; // the maximum number of recent attempts remembered for each user
+
+/// The recently accepted (timestamp, nonce) pairs of a user. Pairs older than the accepted window are forgotten since they
+/// are rejected by the timestamp check anyway.
+#[derive(Default)]
+pub struct ReplayCache {
+    seen: BTreeSet<(u64, [u8; NONCE_LEN])>,
+    floor: u64 // timestamps up to this are rejected because some of them have been evicted
+}
+
+impl ReplayCache {
+    /// returns false if it is a replay
+    fn check_and_insert(&mut self, time_stamp: u64, nonce: [u8; NONCE_LEN], oldest: u64) -> bool {
+        while self.seen.first().is_some_and(|&(x, _)| x < oldest) {
+            self.seen.pop_first();
+        }
+
+        if time_stamp <= self.floor || !self.seen.insert((time_stamp, nonce)) {
+            return false
+        }
+
+        if self.seen.len() > CACHE_SIZE {
+            let (evicted, _) = self.seen.pop_first().unwrap();
+            self.floor = evicted
+        }
+
+        true
+    }
+}
 
 pub struct Server {
-    key: ring::hmac::Key
+    users: Vec<User>,
+    skew: u64, // us
+    failure: Failure
 }
 
 impl Server {
-    pub fn new(key: ring::hmac::Key) -> Self {
-        Self { key }
+    pub fn new(users: Vec<User>, skew: u64, failure: Failure) -> Self {
+        Self { users, skew, failure }
     }
 }
 
 impl<R: api::Runtime> api::Actor<R> for Server {
-    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+    fn spawn(&'static self, runtime: R, mut metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
         let mut mailbox = mailbox.unwrap();
 
         runtime.spawn_task_with_runtime(move |runtime| async move {
             let mut buf = vec![];
-            let header_len = 8 + ALGORITHM.digest_algorithm().output_len; // timestamp (u64) + mac
+            let data_len = 8 + NONCE_LEN; // timestamp (u64) + nonce
+            let header_len = data_len + ALGORITHM.digest_algorithm().output_len;
 
-            while let Some(msg) = mailbox.recv().await {
-                buf.extend_from_slice(&msg);
-                if buf.len() >= header_len {
-                    break
-                }
+            if let Err(event) = self.failure.read(&mut mailbox, &mut buf, header_len).await {
+                log(&metadata, event, None);
+                return self.failure.handle(runtime, metadata, address, mailbox, &buf).await
             }
 
-            // 1. verify timestamp
+            // 1. verify MAC, which also identifies the user
+            let user = match identify(&self.users, &buf[..data_len], &buf[data_len..header_len]) {
+                Some(user) => user,
+                None => {
+                    log(&metadata, "failed attempt", None);
+                    return self.failure.handle(runtime, metadata, address, mailbox, &buf).await
+                }
+            };
+
+            // 2. verify timestamp
             let time_stamp = u64::from_be_bytes(buf[..8].try_into().unwrap());
             let current_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros() as u64;
-            if time_stamp < current_time - 5_000_000 || time_stamp > current_time + 1_000_000 { // older than 5s or earlier than 1s
-                return
-            }
-
-            let last_time = LAST_TIME.load(Ordering::Relaxed);
-            if time_stamp <= last_time {
-                return
+            if time_stamp.abs_diff(current_time) > self.skew {
+                log(&metadata, "expired timestamp of", Some(user));
+                return self.failure.handle(runtime, metadata, address, mailbox, &buf).await
             }
 
-            loop {
-                match LAST_TIME.compare_exchange_weak(last_time, time_stamp, Ordering::SeqCst, Ordering::SeqCst) {
-                    Ok(_) => break,
-                    Err(last_time) if last_time < time_stamp => continue, // LAST_TIME has been updated, but it is still OK as long as time_stamp is still larger
-                    Err(_) => return
-                }
+            // 3. check replay
+            let nonce = buf[8..data_len].try_into().unwrap();
+            if !user.replay.lock().unwrap().check_and_insert(time_stamp, nonce, current_time.saturating_sub(self.skew)) {
+                log(&metadata, "replayed attempt of", Some(user));
+                return self.failure.handle(runtime, metadata, address, mailbox, &buf).await
             }
 
-            // 2. verify MAC
-            if ring::hmac::verify(&self.key, &buf[..8], &buf[8..header_len]).is_err() {
-                if let Some(origin) = metadata.get::<std::net::SocketAddr>("origin_addr") {
-                    eprintln!("auth: failed attempt from {}", origin)
-                } else {
-                    eprintln!("auth: failed attempt")
-                }
-                return // TODO: cut connection
-            }
+            user.accept(&mut metadata);
 
-            // 3. forwarding
+            // 4. forwarding
             let (mut address_next, mailbox_next) = runtime.channel();
-            runtime.spawn_next(0, metadata, address, mailbox_next);
+            runtime.spawn_next(self.failure.main, metadata, address, mailbox_next);
 
             #[allow(clippy::collapsible_if)]
             if buf.len() > header_len {
@@ -116,3 +147,40 @@ impl<R: api::Runtime> api::Actor<R> for Server {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay() {
+        let mut cache = ReplayCache::default();
+        assert!(cache.check_and_insert(100, [1; NONCE_LEN], 0));
+        assert!(!cache.check_and_insert(100, [1; NONCE_LEN], 0));
+        assert!(cache.check_and_insert(100, [2; NONCE_LEN], 0));
+        assert!(cache.check_and_insert(99, [1; NONCE_LEN], 0));
+    }
+
+    #[test]
+    fn expire() {
+        let mut cache = ReplayCache::default();
+        assert!(cache.check_and_insert(100, [1; NONCE_LEN], 0));
+        assert!(cache.check_and_insert(200, [1; NONCE_LEN], 150));
+        assert_eq!(cache.seen.len(), 1);
+        assert!(!cache.check_and_insert(200, [1; NONCE_LEN], 150));
+    }
+
+    #[test]
+    fn evict() {
+        let mut cache = ReplayCache::default();
+        for i in 0..=CACHE_SIZE as u64 {
+            assert!(cache.check_and_insert(1000 + i, [0; NONCE_LEN], 0));
+        }
+        assert_eq!(cache.seen.len(), CACHE_SIZE);
+
+        // the evicted one cannot be told from a replay anymore, so everything up to it is rejected
+        assert!(!cache.check_and_insert(1000, [1; NONCE_LEN], 0));
+        assert!(!cache.check_and_insert(999, [1; NONCE_LEN], 0));
+        assert!(cache.check_and_insert(1001, [1; NONCE_LEN], 0));
+    }
+}
@@ -1,18 +1,41 @@
-use std::sync::atomic::AtomicU64;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}};
+use std::time::{Duration, Instant};
 
+use api::{Address, Mailbox, StatusReporter, StatusWatcher};
 use api::serde::Deserialize;
+use rand::distributions::{Distribution, WeightedIndex};
 
 struct Component;
 
-struct Actor {
-    count: AtomicU64, // the count don't warp. Instead, we calculate the modulo when using. Hopfully u64 won't deplete.
-    n_outputs: usize
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10); // used by probes if `timeout` is not set
+
+enum Method {
+    RoundRobin,
+    Random,
+    LeastConnections,
+    Hash(HashKey)
 }
 
-impl Actor {
-    fn new(n_outputs: usize) -> Self {
-        Self { count: 0.into(), n_outputs }
-    }
+#[derive(Clone, Copy)]
+enum HashKey { Origin, Destination }
+
+struct Actor {
+    method: Method,
+    names: Vec<String>,
+    weights: Vec<u64>,
+    current: Mutex<Vec<i64>>, // the current weights of smooth weighted round robin
+    active: Arc<[AtomicUsize]>, // the number of open streams of each output
+
+    timeout: Option<Duration>,
+    buffer: usize,
+    cooldown: Duration,
+    probe: Option<Duration>,
+    probing: AtomicBool, // whether the probes have been started
+    epoch: Instant,
+    down_until: Vec<AtomicU64> // ms since epoch. Outputs are healthy after that.
 }
 
 impl<R: api::Runtime> api::Component<R> for Component {
@@ -22,16 +45,61 @@ impl<R: api::Runtime> api::Component<R> for Component {
         #[serde(crate="api::serde")]
         struct Config<'a> {
             method: Option<&'a str>,
+            weights: Option<&'a str>, // comma-separated name:weight
+            hash_key: Option<&'a str>,
+
+            timeout: Option<u64>, // ms
+            buffer: Option<usize>,
+            cooldown: Option<u64>, // ms
+            probe: Option<u64>, // ms
+
             outputs: Vec<&'a str>,
             function_name: &'a str,
         }
 
         let config: Config = api::parse_args(&arguments).unwrap();
 
-        match &config.method {
-            None | Some("round_robin") => Box::new(Actor::new(config.outputs.len())),
-            _ => todo!()
+        let method = match config.method.unwrap_or("round_robin") {
+            "round_robin" => Method::RoundRobin,
+            "random" => Method::Random,
+            "least_connections" => Method::LeastConnections,
+            "hash" => Method::Hash(match config.hash_key.unwrap_or("origin_addr") {
+                "origin_addr" => HashKey::Origin,
+                "destination_addr" => HashKey::Destination,
+                x => panic!("unknown balance hash_key: {}", x)
+            }),
+            x => panic!("unknown balance method: {}", x)
+        };
+
+        let mut weights = vec![1; config.outputs.len()];
+        for entry in config.weights.unwrap_or_default().split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (name, weight) = entry.split_once(':').expect("balance weights must be in the form of name:weight");
+            let index = config.outputs.iter().position(|x| *x == name.trim()).unwrap_or_else(|| panic!("balance has no output named {}", name));
+            weights[index] = weight.trim().parse().expect("balance weights must be integers");
         }
+
+        if weights.iter().all(|&x| x == 0) {
+            panic!("balance needs at least one output with a positive weight")
+        }
+
+        if config.probe == Some(0) {
+            panic!("balance probe interval must be positive")
+        }
+
+        Box::new(Actor {
+            method,
+            names: config.outputs.iter().enumerate().map(|(i, x)| if x.is_empty() { format!("#{}", i) } else { x.to_string() }).collect(),
+            current: Mutex::new(vec![0; weights.len()]),
+            active: weights.iter().map(|_| AtomicUsize::new(0)).collect(),
+            timeout: config.timeout.map(Duration::from_millis),
+            buffer: config.buffer.unwrap_or(65536),
+            cooldown: Duration::from_millis(config.cooldown.unwrap_or(30000)),
+            probe: config.probe.map(Duration::from_millis),
+            probing: false.into(),
+            epoch: Instant::now(),
+            down_until: weights.iter().map(|_| AtomicU64::new(0)).collect(),
+            weights
+        })
     }
 
     fn functions(&self) -> &'static [&'static str] {
@@ -45,13 +113,273 @@ impl<R: api::Runtime> api::Component<R> for Component {
 
 impl<R: api::Runtime> api::Actor<R> for Actor {
     fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
-        let next = self.count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        let next = (next % self.n_outputs as u64) as usize;
-        let (forward_address, forward_mailbox) = runtime.channel();
-        let (backward_address, backward_mailbox) = runtime.channel();
-        runtime.spawn_next(next, metadata, backward_address, forward_mailbox);
-        runtime.spawn_task(api::pass(address, Some(backward_mailbox)));
-        runtime.spawn_task(api::pass(Some(forward_address), mailbox));
+        if let Some(interval) = self.probe {
+            if !self.probing.swap(true, Ordering::Relaxed) {
+                for i in (0..self.weights.len()).filter(|&i| self.weights[i] > 0) {
+                    runtime.spawn_task_with_runtime(move |runtime| self.probe(runtime, i, interval))
+                }
+            }
+        }
+
+        runtime.spawn_task_with_runtime(move |runtime| self.run(runtime, metadata, address, mailbox))
+    }
+}
+
+enum Outcome { Commit, Failover }
+
+impl Actor {
+    /// Try the outputs one by one until one connects. Messages are passed through immediately, and also kept for replaying
+    /// on the next output in case of failure. The stream commits to the current output once it reports connected, sends
+    /// anything back, drops its status reporter, or `buffer` is exceeded. It fails over when the output reports a failure,
+    /// or, if `timeout` is set, none of these happens in time.
+    async fn run<R: api::Runtime>(&'static self, runtime: R, mut metadata: api::MetaData, address: Option<R::Address>, mut mailbox: Option<R::Mailbox>) {
+        let upstream = metadata.get::<R::StatusReporter>("status_reporter").cloned();
+        metadata.take::<R::StatusReporter>("status_reporter");
+
+        let mut tried = vec![];
+        let mut replay: Vec<Box<[u8]>> = vec![];
+        let mut replay_size = 0;
+        let mut closed = mailbox.is_none();
+        let mut last_failure = api::Status::Failed;
+
+        loop {
+            let next = match self.select(&metadata, &tried) {
+                Some(next) => next,
+                None => {
+                    eprintln!("balance: all outputs failed");
+                    if let Some(upstream) = &upstream {
+                        upstream.report(last_failure)
+                    }
+                    return
+                }
+            };
+            tried.push(next);
+
+            // the stream is counted until both directions finish
+            let guard = Arc::new(Active::new(self.active.clone(), next));
+            let (reporter, mut watcher) = runtime.status_channel();
+            let (mut forward_address, forward_mailbox) = runtime.channel();
+            let (backward_address, mut backward_mailbox) = runtime.channel();
+            let mut attempt_metadata = metadata.clone();
+            attempt_metadata.set("status_reporter".into(), reporter);
+            runtime.spawn_next(next, attempt_metadata, backward_address, forward_mailbox);
+
+            let deadline = self.timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+            for msg in &replay {
+                if !send_before(deadline, &mut forward_address, msg.clone()).await {
+                    break // wait for the status or the timeout below
+                }
+            }
+
+            let mut reply = None;
+            let outcome = loop {
+                tokio::select! {
+                    biased;
+                    status = watcher.wait() => match status {
+                        Some(api::Status::Connected) | None => break Outcome::Commit,
+                        Some(status) => {
+                            last_failure = status;
+                            break Outcome::Failover
+                        }
+                    },
+                    msg = backward_mailbox.recv() => {
+                        reply = msg;
+                        break Outcome::Commit
+                    }
+                    msg = recv(&mut mailbox), if !closed => match msg {
+                        Some(msg) => {
+                            replay_size += msg.len();
+                            if replay_size > self.buffer {
+                                send_before(deadline, &mut forward_address, msg).await;
+                                break Outcome::Commit
+                            }
+                            replay.push(msg.clone());
+                            send_before(deadline, &mut forward_address, msg).await;
+                        }
+                        None => closed = true
+                    },
+                    _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                        eprintln!("balance: output {} timed out", self.names[next]);
+                        last_failure = api::Status::TimedOut;
+                        break Outcome::Failover
+                    }
+                }
+            };
+
+            if let Outcome::Failover = outcome {
+                self.mark_down(next);
+                continue
+            }
+
+            // keep the upstream informed of the final status
+            runtime.spawn_task(async move {
+                let status = watcher.wait().await;
+                if let Some(status) = status {
+                    if status == api::Status::Connected {
+                        self.mark_up(next)
+                    }
+                    if let Some(upstream) = upstream {
+                        upstream.report(status)
+                    }
+                }
+            });
+
+            let mut address = address;
+            if let (Some(msg), Some(address)) = (reply, address.as_mut()) {
+                if address.send(msg).await.is_err() {
+                    return
+                }
+            }
+
+            runtime.spawn_task({
+                let guard = guard.clone();
+                async move { api::pass(address, Some(backward_mailbox)).await; drop(guard) }
+            });
+
+            if !closed {
+                api::pass(Some(forward_address), mailbox).await
+            }
+            drop(guard);
+            return
+        }
+    }
+
+    /// Actively check an output by opening an empty stream and waiting for its status, which requires the output to
+    /// report one (e.g. `tcp` with a fixed destination).
+    async fn probe<R: api::Runtime>(&self, runtime: R, index: usize, interval: Duration) {
+        while !matches!(runtime.get_runlevel(), api::RunLevel::Shut) {
+            let (reporter, mut watcher) = runtime.status_channel();
+            let (forward_address, forward_mailbox) = runtime.channel();
+            let (backward_address, backward_mailbox) = runtime.channel();
+            let mut metadata = api::MetaData::default();
+            metadata.set("status_reporter".into(), reporter);
+            runtime.spawn_next(index, metadata, backward_address, forward_mailbox);
+
+            match tokio::time::timeout(self.timeout.unwrap_or(PROBE_TIMEOUT), watcher.wait()).await {
+                Ok(Some(api::Status::Connected)) => self.mark_up(index),
+                Ok(None) => {} // the output does not report its status
+                _ => self.mark_down(index)
+            }
+            drop((forward_address, backward_mailbox));
+
+            tokio::time::sleep(interval).await
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    fn is_up(&self, index: usize) -> bool {
+        self.down_until[index].load(Ordering::Relaxed) <= self.now()
+    }
+
+    fn mark_down(&self, index: usize) {
+        let until = self.now() + self.cooldown.as_millis() as u64;
+        if self.down_until[index].swap(until, Ordering::Relaxed) <= self.now() {
+            eprintln!("balance: output {} is down", self.names[index])
+        }
+    }
+
+    fn mark_up(&self, index: usize) {
+        if self.down_until[index].swap(0, Ordering::Relaxed) > self.now() {
+            eprintln!("balance: output {} is up", self.names[index])
+        }
+    }
+
+    /// Choose among the outputs that are not tried yet, preferring healthy ones. Returns None if all are tried.
+    fn select(&self, metadata: &api::MetaData, tried: &[usize]) -> Option<usize> {
+        let candidates: Vec<bool> = (0..self.weights.len()).map(|i| self.weights[i] > 0 && !tried.contains(&i)).collect();
+        let healthy: Vec<bool> = (0..self.weights.len()).map(|i| candidates[i] && self.is_up(i)).collect();
+        let mask = if healthy.contains(&true) { healthy } else { candidates };
+        if !mask.contains(&true) {
+            return None
+        }
+
+        Some(match self.method {
+            Method::RoundRobin => self.round_robin(&mask),
+            Method::Random => self.random(&mask),
+            Method::LeastConnections => self.least_connections(&mask),
+            Method::Hash(key) => self.hash(key, metadata, &mask).unwrap_or_else(|| self.round_robin(&mask))
+        })
+    }
+
+    /// smooth weighted round robin as in nginx, which interleaves the outputs instead of sending bursts to each
+    fn round_robin(&self, mask: &[bool]) -> usize {
+        let mut current = self.current.lock().unwrap();
+        let mut total = 0;
+        for i in (0..self.weights.len()).filter(|&i| mask[i]) {
+            current[i] += self.weights[i] as i64;
+            total += self.weights[i] as i64
+        }
+        let next = (0..self.weights.len()).filter(|&i| mask[i]).max_by_key(|&i| (current[i], std::cmp::Reverse(i))).unwrap();
+        current[next] -= total;
+        next
+    }
+
+    fn random(&self, mask: &[bool]) -> usize {
+        let weights = (0..self.weights.len()).map(|i| if mask[i] { self.weights[i] } else { 0 });
+        WeightedIndex::new(weights).unwrap().sample(&mut rand::thread_rng())
+    }
+
+    /// the output with the fewest open streams relative to its weight
+    fn least_connections(&self, mask: &[bool]) -> usize {
+        (0..self.weights.len()).filter(|&i| mask[i]).min_by(|&i, &j| {
+            let (a, b) = (self.active[i].load(Ordering::Relaxed) as u128, self.active[j].load(Ordering::Relaxed) as u128);
+            (a * self.weights[j] as u128).cmp(&(b * self.weights[i] as u128))
+        }).unwrap()
+    }
+
+    /// Rendezvous hashing, so only the streams of an output move when it is added, removed, or down. Returns None if
+    /// the metadata does not have the key.
+    fn hash(&self, key: HashKey, metadata: &api::MetaData, mask: &[bool]) -> Option<usize> {
+        let mut hasher = DefaultHasher::new();
+        match key {
+            HashKey::Origin => metadata.get::<SocketAddr>("origin_addr")?.ip().hash(&mut hasher), // ignore the port
+            HashKey::Destination => metadata.get::<String>("destination_addr")?.hash(&mut hasher)
+        }
+        let key = hasher.finish();
+
+        (0..self.weights.len()).filter(|&i| mask[i]).map(|i| {
+            let mut hasher = DefaultHasher::new();
+            (key, i).hash(&mut hasher);
+            let u = ((hasher.finish() >> 11) as f64 + 0.5) / (1u64 << 53) as f64; // uniform in (0, 1)
+            (i, self.weights[i] as f64 / -u.ln())
+        }).max_by(|(_, a), (_, b)| a.total_cmp(b)).map(|(i, _)| i)
+    }
+}
+
+/// send a message unless the deadline passes first. Returns false on timeout.
+async fn send_before(deadline: Option<tokio::time::Instant>, address: &mut impl api::Address, msg: Box<[u8]>) -> bool {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, address.send(msg)).await.is_ok(),
+        None => {
+            let _ = address.send(msg).await;
+            true
+        }
+    }
+}
+
+async fn recv(mailbox: &mut Option<impl api::Mailbox>) -> Option<Box<[u8]>> {
+    mailbox.as_mut()?.recv().await
+}
+
+/// counts an open stream of an output until dropped
+struct Active {
+    active: Arc<[AtomicUsize]>,
+    index: usize
+}
+
+impl Active {
+    fn new(active: Arc<[AtomicUsize]>, index: usize) -> Self {
+        active[index].fetch_add(1, Ordering::Relaxed);
+        Self { active, index }
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.active[self.index].fetch_sub(1, Ordering::Relaxed);
     }
 }
 
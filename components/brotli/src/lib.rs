@@ -0,0 +1,155 @@
+use api::serde::Deserialize;
+use std::io::Write;
+
+use brotli_lib::{CompressorWriter, BrotliDecompressStream, BrotliResult, BrotliState, HeapAlloc, HuffmanCode};
+
+struct Component;
+
+#[derive(Clone, Copy)]
+enum Role { Encoder, Decoder }
+
+struct Actor {
+    level: u32,
+    window: u32,
+    role: Role
+}
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            level: Option<u32>,
+            window: Option<u32>,
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("brotli must have exactly 1 output")
+        }
+
+        Box::new(Actor {
+            level: config.level.unwrap_or(5),
+            window: config.window.unwrap_or(22),
+            role: match config.function_name {
+                "brotli_deflate" => Role::Encoder,
+                "brotli_inflate" => Role::Decoder,
+                _ => unreachable!()
+            }
+        })
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["brotli_deflate", "brotli_inflate"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "brotli"
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Actor {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        if metadata.get::<bool>("datagram").copied().unwrap_or(false) {
+            eprintln!("WARNING: the brotli module is not designed for datagrams")
+        }
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        match self.role {
+            Role::Encoder => {
+                runtime.spawn_task(self.deflate(forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(self.inflate(address.expect("no address"), backward_mailbox));
+            }
+            Role::Decoder => {
+                runtime.spawn_task(self.inflate(forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(self.deflate(address.expect("no address"), backward_mailbox));
+            }
+        }
+    }
+
+    fn spawn_composite(&'static self, runtime: R, _metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        match self.role {
+            Role::Encoder => runtime.spawn_task(self.deflate(address.expect("no address"), mailbox.expect("no mailbox"))),
+            Role::Decoder => runtime.spawn_task(self.inflate(address.expect("no address"), mailbox.expect("no mailbox")))
+        }
+    }
+}
+
+impl Actor {
+    /// each message is flushed so that the peer can decompress it without waiting for more
+    async fn deflate(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut compressor = CompressorWriter::new(vec![], 4096, self.level, self.window);
+
+        let mut started = false;
+        while let Some(msg) = mail.recv().await {
+            started = true;
+            if let Err(e) = compressor.write_all(&msg).and_then(|_| compressor.flush()) {
+                return eprintln!("brotli: {}", e)
+            }
+
+            let result = std::mem::take(compressor.get_mut());
+            if !result.is_empty() && addr.send(result.into()).await.is_err() {
+                return
+            }
+        }
+
+        // write the last block so the peer can tell it from a truncated stream. Directions that never carried data stay
+        // empty.
+        if !started {
+            return
+        }
+        let end = compressor.into_inner();
+        if !end.is_empty() {
+            let _ = addr.send(end.into()).await;
+        }
+    }
+
+    async fn inflate(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut state = BrotliState::new(HeapAlloc::new(0), HeapAlloc::new(0), HeapAlloc::new(HuffmanCode::default()));
+        let mut total_out = 0;
+
+        let mut buffer = vec![0; 65536];
+        let mut started = false;
+        while let Some(msg) = mail.recv().await {
+            started = true;
+            let (mut avail_in, mut input_offset) = (msg.len(), 0);
+
+            loop {
+                let (mut avail_out, mut output_offset) = (buffer.len(), 0);
+                let result = BrotliDecompressStream(&mut avail_in, &mut input_offset, &msg, &mut avail_out, &mut output_offset, &mut buffer, &mut total_out, &mut state);
+
+                if output_offset > 0 && addr.send(buffer[..output_offset].into()).await.is_err() {
+                    return
+                }
+
+                match result {
+                    BrotliResult::NeedsMoreOutput => continue,
+                    BrotliResult::NeedsMoreInput if output_offset > 0 => continue, // the decoder may still hold some output
+                    BrotliResult::NeedsMoreInput => break,
+                    BrotliResult::ResultSuccess => { // the stream is finished
+                        if avail_in > 0 {
+                            eprintln!("brotli: trailing data after the end of the stream")
+                        }
+                        return
+                    }
+                    BrotliResult::ResultFailure => return eprintln!("brotli: corrupted data")
+                }
+            }
+        }
+
+        if started {
+            eprintln!("brotli: stream ended without the end mark, it might be truncated")
+        }
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
@@ -0,0 +1,207 @@
+mod pcapng;
+
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU16, Ordering}};
+use std::time::SystemTime;
+
+use api::{Address, Mailbox};
+use api::serde::Deserialize;
+
+const MAX_SEGMENT: usize = 65000; // payload per packet, so the IP length fits in u16
+
+struct Component;
+
+struct Actor {
+    file: Mutex<File>,
+    failed: AtomicBool, // stop capturing after the first write error
+    next_port: AtomicU16 // synthetic ports for streams without `origin_addr`
+}
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            path: &'a str,
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("capture must have exactly 1 output")
+        }
+
+        let mut file = File::create(config.path).unwrap_or_else(|e| panic!("capture cannot create {}: {}", config.path, e));
+        file.write_all(&pcapng::header()).unwrap_or_else(|e| panic!("capture cannot write {}: {}", config.path, e));
+
+        Box::new(Actor { file: Mutex::new(file), failed: false.into(), next_port: AtomicU16::new(49152) })
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["capture"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "capture"
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction { Forward, Backward }
+
+/// The synthetic connection of a stream. The origin is the client.
+struct Stream {
+    origin: SocketAddr,
+    destination: SocketAddr,
+    datagram: bool,
+    state: Mutex<State>
+}
+
+struct State {
+    seq: [u32; 2], // the next sequence number of each direction
+    comment: Option<String> // attached to the first packet
+}
+
+impl<R: api::Runtime> api::Actor<R> for Actor {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let stream = Arc::new(self.stream(&metadata));
+        if !stream.datagram {
+            self.handshake(&stream)
+        }
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        runtime.spawn_task(self.pass(stream.clone(), Direction::Forward, forward_address, mailbox.expect("no mailbox")));
+        if let Some(address) = address { // datagram sources may not accept replies
+            runtime.spawn_task(self.pass(stream, Direction::Backward, address, backward_mailbox));
+        }
+    }
+}
+
+impl Actor {
+    fn stream(&self, metadata: &api::MetaData) -> Stream {
+        let origin = metadata.get::<SocketAddr>("origin_addr").copied().unwrap_or_else(|| {
+            let port = self.next_port.fetch_add(1, Ordering::Relaxed);
+            SocketAddr::new(Ipv4Addr::new(198, 18, 0, 1).into(), port)
+        });
+
+        // hostnames are mapped into 198.19.0.0/16 (reserved for benchmarking) and written in the comment instead
+        let host = metadata.get::<String>("destination_addr");
+        let port = metadata.get::<u16>("destination_port").copied().unwrap_or(0);
+        let ip = match host.map(|x| x.parse::<IpAddr>()) {
+            Some(Ok(ip)) => ip,
+            Some(Err(_)) => {
+                let mut hasher = DefaultHasher::new();
+                host.hash(&mut hasher);
+                let [a, b] = (hasher.finish() as u16).to_be_bytes();
+                Ipv4Addr::new(198, 19, a, b).into()
+            }
+            None => Ipv4Addr::new(198, 18, 0, 2).into()
+        };
+
+        let mut comment = vec![];
+        if let Some(id) = metadata.get::<u64>("stream_id") {
+            comment.push(format!("stream {}", id))
+        }
+        if let Some(host) = host.filter(|x| x.parse::<IpAddr>().is_err()) {
+            comment.push(format!("destination {}:{}", host, port))
+        }
+
+        let (origin, destination) = same_family(origin, SocketAddr::new(ip, port));
+        Stream {
+            origin, destination,
+            datagram: metadata.get::<bool>("datagram").copied().unwrap_or(false),
+            state: Mutex::new(State { seq: [0, 0], comment: Some(comment.join(", ")).filter(|x| !x.is_empty()) })
+        }
+    }
+
+    async fn pass(&self, stream: Arc<Stream>, direction: Direction, mut addr: impl Address, mut mail: impl Mailbox) {
+        while let Some(msg) = mail.recv().await {
+            self.record(&stream, direction, &msg);
+            if addr.send(msg).await.is_err() {
+                return
+            }
+        }
+
+        if !stream.datagram {
+            self.finish(&stream, direction)
+        }
+    }
+
+    fn handshake(&self, stream: &Stream) {
+        let mut state = stream.state.lock().unwrap();
+        let (client, server) = (stream.origin, stream.destination);
+        self.write(&mut state, pcapng::tcp(client, server, 0, 0, pcapng::SYN, &[]));
+        self.write(&mut state, pcapng::tcp(server, client, 0, 1, pcapng::SYN | pcapng::ACK, &[]));
+        self.write(&mut state, pcapng::tcp(client, server, 1, 1, pcapng::ACK, &[]));
+        state.seq = [1, 1];
+    }
+
+    fn record(&self, stream: &Stream, direction: Direction, msg: &[u8]) {
+        let mut state = stream.state.lock().unwrap();
+        let (src, dst, i) = endpoints(stream, direction);
+        for chunk in msg.chunks(MAX_SEGMENT) {
+            let packet = if stream.datagram {
+                pcapng::udp(src, dst, chunk)
+            } else {
+                let (seq, ack) = (state.seq[i], state.seq[1 - i]);
+                state.seq[i] = seq.wrapping_add(chunk.len() as _);
+                pcapng::tcp(src, dst, seq, ack, pcapng::PSH | pcapng::ACK, chunk)
+            };
+            self.write(&mut state, packet)
+        }
+    }
+
+    fn finish(&self, stream: &Stream, direction: Direction) {
+        let mut state = stream.state.lock().unwrap();
+        let (src, dst, i) = endpoints(stream, direction);
+        let (seq, ack) = (state.seq[i], state.seq[1 - i]);
+        state.seq[i] = seq.wrapping_add(1);
+        self.write(&mut state, pcapng::tcp(src, dst, seq, ack, pcapng::FIN | pcapng::ACK, &[]))
+    }
+
+    fn write(&self, state: &mut State, packet: Vec<u8>) {
+        if self.failed.load(Ordering::Relaxed) {
+            return
+        }
+
+        let block = pcapng::packet(SystemTime::now(), &packet, state.comment.take().as_deref());
+        if let Err(e) = self.file.lock().unwrap().write_all(&block) {
+            eprintln!("capture: write error, stop capturing: {}", e);
+            self.failed.store(true, Ordering::Relaxed)
+        }
+    }
+}
+
+/// the source, destination, and the index of the sequence number of a direction
+fn endpoints(stream: &Stream, direction: Direction) -> (SocketAddr, SocketAddr, usize) {
+    match direction {
+        Direction::Forward => (stream.origin, stream.destination, 0),
+        Direction::Backward => (stream.destination, stream.origin, 1)
+    }
+}
+
+/// use IPv4 if both addresses can be, otherwise map the IPv4 one into IPv6
+fn same_family(a: SocketAddr, b: SocketAddr) -> (SocketAddr, SocketAddr) {
+    let canonical = |x: SocketAddr| SocketAddr::new(x.ip().to_canonical(), x.port());
+    let (a, b) = (canonical(a), canonical(b));
+    let v6 = |x: SocketAddr| match x.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(ip.to_ipv6_mapped().into(), x.port()),
+        IpAddr::V6(_) => x
+    };
+    match (a.ip(), b.ip()) {
+        (IpAddr::V4(_), IpAddr::V4(_)) => (a, b),
+        _ => (v6(a), v6(b))
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
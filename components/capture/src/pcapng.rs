@@ -0,0 +1,226 @@
+//! Just enough of pcapng and IP/TCP/UDP to make Wireshark happy. All blocks are written in little endian, while packet
+//! headers are in network byte order.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LINKTYPE_RAW: u16 = 101; // packets begin with an IPv4 or IPv6 header
+
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+pub const FIN: u8 = 0x01;
+pub const SYN: u8 = 0x02;
+pub const PSH: u8 = 0x08;
+pub const ACK: u8 = 0x10;
+
+/// the Section Header Block and the Interface Description Block that start the file
+pub fn header() -> Vec<u8> {
+    let mut shb = vec![];
+    shb.extend_from_slice(&0x1A2B3C4Du32.to_le_bytes()); // byte-order magic
+    shb.extend_from_slice(&1u16.to_le_bytes()); // major version
+    shb.extend_from_slice(&0u16.to_le_bytes()); // minor version
+    shb.extend_from_slice(&(-1i64).to_le_bytes()); // section length, unknown
+
+    let mut idb = vec![];
+    idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    idb.extend_from_slice(&0u16.to_le_bytes()); // reserved
+    idb.extend_from_slice(&0u32.to_le_bytes()); // snap length, unlimited
+
+    let mut buf = block(0x0A0D0D0A, &shb);
+    buf.extend_from_slice(&block(0x00000001, &idb));
+    buf
+}
+
+/// an Enhanced Packet Block with microsecond timestamp and an optional comment
+pub fn packet(time: SystemTime, packet: &[u8], comment: Option<&str>) -> Vec<u8> {
+    let micros = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+
+    let mut body = vec![];
+    body.extend_from_slice(&0u32.to_le_bytes()); // interface id
+    body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(micros as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // captured length
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes()); // original length
+    body.extend_from_slice(packet);
+    pad(&mut body);
+
+    if let Some(comment) = comment {
+        body.extend_from_slice(&1u16.to_le_bytes()); // opt_comment
+        body.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        body.extend_from_slice(comment.as_bytes());
+        pad(&mut body);
+        body.extend_from_slice(&[0; 4]); // opt_endofopt
+    }
+
+    block(0x00000006, &body)
+}
+
+fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+    let len = (12 + body.len()) as u32;
+    let mut buf = Vec::with_capacity(len as _);
+    buf.extend_from_slice(&kind.to_le_bytes());
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(body);
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0)
+}
+
+/// an IP packet carrying a TCP segment
+pub fn tcp(src: SocketAddr, dst: SocketAddr, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(5 << 4); // data offset: 5 words, no options
+    segment.push(flags);
+    segment.extend_from_slice(&u16::MAX.to_be_bytes()); // window
+    segment.extend_from_slice(&[0; 4]); // checksum and urgent pointer
+    segment.extend_from_slice(payload);
+
+    let checksum = checksum(src.ip(), dst.ip(), PROTOCOL_TCP, &segment);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    ip(src.ip(), dst.ip(), PROTOCOL_TCP, &segment)
+}
+
+/// an IP packet carrying a UDP datagram
+pub fn udp(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(8 + payload.len());
+    datagram.extend_from_slice(&src.port().to_be_bytes());
+    datagram.extend_from_slice(&dst.port().to_be_bytes());
+    datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0; 2]); // checksum
+    datagram.extend_from_slice(payload);
+
+    let checksum = match checksum(src.ip(), dst.ip(), PROTOCOL_UDP, &datagram) {
+        0 => 0xffff, // zero means no checksum in UDP
+        x => x
+    };
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+    ip(src.ip(), dst.ip(), PROTOCOL_UDP, &datagram)
+}
+
+/// `src` and `dst` must be of the same family
+fn ip(src: IpAddr, dst: IpAddr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = vec![];
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            buf.push(0x45); // version 4, 5 words header
+            buf.push(0); // DSCP and ECN
+            buf.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+            buf.extend_from_slice(&[0, 0, 0x40, 0]); // identification, don't fragment
+            buf.push(64); // TTL
+            buf.push(protocol);
+            buf.extend_from_slice(&[0; 2]); // checksum
+            buf.extend_from_slice(&src.octets());
+            buf.extend_from_slice(&dst.octets());
+            let checksum = !fold(sum(&buf));
+            buf[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            buf.extend_from_slice(&0x60000000u32.to_be_bytes()); // version 6, no traffic class or flow label
+            buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            buf.push(protocol);
+            buf.push(64); // hop limit
+            buf.extend_from_slice(&src.octets());
+            buf.extend_from_slice(&dst.octets());
+        }
+        _ => unreachable!()
+    }
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// the TCP/UDP checksum, including the pseudo header
+fn checksum(src: IpAddr, dst: IpAddr, protocol: u8, payload: &[u8]) -> u16 {
+    let mut pseudo = vec![];
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            pseudo.extend_from_slice(&src.octets());
+            pseudo.extend_from_slice(&dst.octets());
+            pseudo.extend_from_slice(&[0, protocol]);
+            pseudo.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            pseudo.extend_from_slice(&src.octets());
+            pseudo.extend_from_slice(&dst.octets());
+            pseudo.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, protocol]);
+        }
+        _ => unreachable!()
+    }
+    !fold(sum(&pseudo) + sum(payload))
+}
+
+/// the one's complement sum of 16-bit words, not folded yet
+fn sum(data: &[u8]) -> u64 {
+    data.chunks(2).map(|x| u16::from_be_bytes([x[0], x.get(1).copied().unwrap_or(0)]) as u64).sum()
+}
+
+fn fold(mut sum: u64) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16)
+    }
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4(port: u16) -> SocketAddr {
+        SocketAddr::new([192, 168, 0, 1].into(), port)
+    }
+
+    fn v6(port: u16) -> SocketAddr {
+        SocketAddr::new("2001:db8::1".parse().unwrap(), port)
+    }
+
+    /// the checksum over the data with the checksum in place, which is zero if it is correct
+    fn verify(src: SocketAddr, dst: SocketAddr, protocol: u8, packet: &[u8]) -> u16 {
+        let header_len = if src.is_ipv4() { 20 } else { 40 };
+        checksum(src.ip(), dst.ip(), protocol, &packet[header_len..])
+    }
+
+    #[test]
+    fn ipv4_header() {
+        // the example from Wikipedia: 4500 0073 0000 4000 4011 b861 c0a8 0001 c0a8 00c7
+        let packet = ip([192, 168, 0, 1].into(), [192, 168, 0, 199].into(), PROTOCOL_UDP, &[0; 95]);
+        assert_eq!(packet[10..12], [0xb8, 0x61]);
+        assert_eq!(fold(sum(&packet[..20])), 0xffff);
+    }
+
+    #[test]
+    fn tcp_checksum() {
+        for payload in [&b""[..], b"hello", b"hello!"] { // odd lengths are padded with zero
+            let (src, dst) = (v4(1234), v4(80));
+            assert_eq!(verify(src, dst, PROTOCOL_TCP, &tcp(src, dst, 1, 2, ACK | PSH, payload)), 0);
+            let (src, dst) = (v6(1234), v6(80));
+            assert_eq!(verify(src, dst, PROTOCOL_TCP, &tcp(src, dst, 1, 2, ACK | PSH, payload)), 0);
+        }
+    }
+
+    #[test]
+    fn udp_checksum() {
+        for payload in [&b""[..], b"hello", b"hello!"] {
+            let (src, dst) = (v4(1234), v4(53));
+            assert_eq!(verify(src, dst, PROTOCOL_UDP, &udp(src, dst, payload)), 0);
+            let (src, dst) = (v6(1234), v6(53));
+            assert_eq!(verify(src, dst, PROTOCOL_UDP, &udp(src, dst, payload)), 0);
+        }
+    }
+
+    #[test]
+    fn udp_zero_checksum() {
+        // a trailing word equal to the checksum of the datagram without it makes the sum zero
+        let (src, dst) = (v4(1234), v4(53));
+        let packet = udp(src, dst, &[0, 0]);
+        let packet = udp(src, dst, &packet[26..28]);
+        assert_eq!(packet[26..28], [0xff, 0xff]);
+    }
+}
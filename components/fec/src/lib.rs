@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use api::serde::Deserialize;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use tokio::time::Instant;
+
+struct Component;
+
+#[derive(Clone, Copy)]
+enum Role { Encoder, Decoder }
+
+const HEADER_LEN: usize = 7; // group (u32) + index (u8) + number of data shards (u8) + number of parity shards (u8)
+const MAX_DATAGRAM: usize = 65507; // the largest UDP payload over IPv4
+const MAX_PAYLOAD: usize = MAX_DATAGRAM - HEADER_LEN - 2; // parity shards carry the length of each datagram in 2 more bytes
+const GROUP_WINDOW: u32 = 64; // the number of recent groups that the decoder keeps for reconstruction
+
+struct Actor {
+    data_shards: usize,
+    parity_shards: usize,
+    timeout: Duration,
+    codec: ReedSolomon, // for full groups. Partial groups build their own.
+    role: Role
+}
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            data: Option<usize>,
+            parity: Option<usize>,
+            timeout: Option<u64>, // ms
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("fec must have exactly 1 output")
+        }
+
+        let data_shards = config.data.unwrap_or(10);
+        let parity_shards = config.parity.unwrap_or(3);
+        if data_shards == 0 || parity_shards == 0 || data_shards + parity_shards > 255 {
+            panic!("fec needs at least 1 data shard and 1 parity shard, and at most 255 shards in total")
+        }
+
+        Box::new(Actor {
+            data_shards, parity_shards,
+            timeout: Duration::from_millis(config.timeout.unwrap_or(20)),
+            codec: ReedSolomon::new(data_shards, parity_shards).unwrap(),
+            role: match config.function_name {
+                "fec_encode" => Role::Encoder,
+                "fec_decode" => Role::Decoder,
+                _ => unreachable!()
+            }
+        })
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["fec_encode", "fec_decode"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "fec"
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Actor {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        if !metadata.get::<bool>("datagram").copied().unwrap_or(false) {
+            eprintln!("WARNING: the fec module is designed for datagrams")
+        }
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        match self.role {
+            Role::Encoder => {
+                runtime.spawn_task(self.encode(forward_address, mailbox.expect("no mailbox")));
+                if let Some(address) = address { // datagram sources may not accept replies
+                    runtime.spawn_task(decode(address, backward_mailbox));
+                }
+            }
+            Role::Decoder => {
+                runtime.spawn_task(decode(forward_address, mailbox.expect("no mailbox")));
+                if let Some(address) = address {
+                    runtime.spawn_task(self.encode(address, backward_mailbox));
+                }
+            }
+        }
+    }
+
+    fn spawn_composite(&'static self, runtime: R, _metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        match self.role {
+            Role::Encoder => runtime.spawn_task(self.encode(address.expect("no address"), mailbox.expect("no mailbox"))),
+            Role::Decoder => runtime.spawn_task(decode(address.expect("no address"), mailbox.expect("no mailbox")))
+        }
+    }
+}
+
+fn packet(group: u32, index: usize, n_data: usize, n_parity: usize, payload: &[u8]) -> Box<[u8]> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+    buf.extend_from_slice(&group.to_be_bytes());
+    buf.extend_from_slice(&[index as u8, n_data as u8, n_parity as u8]);
+    buf.extend_from_slice(payload);
+    buf.into()
+}
+
+/// pad a datagram into a shard of `len` bytes, with its real length in the first two bytes
+fn to_shard(payload: &[u8], len: usize) -> Vec<u8> {
+    let mut shard = Vec::with_capacity(len);
+    shard.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    shard.extend_from_slice(payload);
+    shard.resize(len, 0);
+    shard
+}
+
+impl Actor {
+    /// Datagrams are sent immediately with a header. Parity packets are sent after every `data_shards` datagrams, or
+    /// when the group is not completed within `timeout` after its first datagram.
+    async fn encode(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut group: u32 = 0;
+        let mut pending: Vec<Box<[u8]>> = Vec::with_capacity(self.data_shards);
+        let mut deadline = Instant::now();
+
+        loop {
+            // on timeout, treat it like the end of the stream to flush the partial group
+            let msg = if pending.is_empty() {
+                let msg = mail.recv().await;
+                deadline = Instant::now() + self.timeout;
+                msg
+            } else {
+                tokio::time::timeout_at(deadline, mail.recv()).await.unwrap_or_default()
+            };
+
+            let msg = match msg {
+                Some(msg) if msg.len() > MAX_PAYLOAD => {
+                    eprintln!("fec: datagram too large, dropped");
+                    continue
+                }
+                Some(msg) => msg,
+                None => {
+                    if !pending.is_empty() {
+                        for parity in self.parity(group, &pending) {
+                            if addr.send(parity).await.is_err() {
+                                return
+                            }
+                        }
+                        pending.clear();
+                        group = group.wrapping_add(1);
+                        continue
+                    }
+                    return
+                }
+            };
+
+            if addr.send(packet(group, pending.len(), 0, 0, &msg)).await.is_err() {
+                return
+            }
+
+            pending.push(msg);
+            if pending.len() == self.data_shards {
+                for parity in self.parity(group, &pending) {
+                    if addr.send(parity).await.is_err() {
+                        return
+                    }
+                }
+                pending.clear();
+                group = group.wrapping_add(1);
+            }
+        }
+    }
+
+    fn parity(&self, group: u32, data: &[Box<[u8]>]) -> Vec<Box<[u8]>> {
+        let len = data.iter().map(|x| x.len()).max().unwrap() + 2;
+        let mut shards: Vec<_> = data.iter().map(|x| to_shard(x, len)).collect();
+        shards.resize(data.len() + self.parity_shards, vec![0; len]);
+
+        if data.len() == self.data_shards {
+            self.codec.encode(&mut shards).unwrap()
+        } else {
+            ReedSolomon::new(data.len(), self.parity_shards).unwrap().encode(&mut shards).unwrap()
+        }
+
+        shards[data.len()..].iter().enumerate()
+            .map(|(i, shard)| packet(group, data.len() + i, data.len(), self.parity_shards, shard))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Group {
+    data: Vec<Option<Box<[u8]>>>,
+    parity: Vec<Option<Box<[u8]>>>,
+    layout: Option<(usize, usize)>, // (data shards, parity shards), learned from parity packets
+    done: bool // all datagrams are either received or reconstructed
+}
+
+impl Group {
+    /// try to reconstruct the missing datagrams. Returns them if succeeded.
+    fn reconstruct(&mut self) -> Option<Vec<Box<[u8]>>> {
+        let (n_data, n_parity) = self.layout?;
+        self.data.resize(n_data, None);
+        self.parity.resize(n_parity, None);
+
+        let n_received = self.data.iter().chain(&self.parity).filter(|x| x.is_some()).count();
+        if self.data.iter().all(|x| x.is_some()) {
+            self.done = true;
+            self.data.clear();
+            self.parity.clear();
+            return Some(vec![])
+        }
+        if n_received < n_data {
+            return None
+        }
+
+        let len = self.parity.iter().flatten().next()?.len();
+        if len < 2 || self.parity.iter().flatten().any(|x| x.len() != len) || self.data.iter().flatten().any(|x| x.len() > len - 2) {
+            eprintln!("fec: malformed group");
+            self.done = true;
+            return None
+        }
+
+        let mut shards: Vec<Option<Vec<u8>>> = self.data.iter()
+            .map(|x| x.as_ref().map(|x| to_shard(x, len)))
+            .chain(self.parity.iter().map(|x| x.as_ref().map(|x| x.to_vec())))
+            .collect();
+
+        self.done = true;
+        ReedSolomon::new(n_data, n_parity).ok()?.reconstruct_data(&mut shards).ok()?;
+
+        let recovered = self.data.iter().zip(shards).filter(|(x, _)| x.is_none()).filter_map(|(_, shard)| {
+            let shard = shard?;
+            let len = u16::from_be_bytes([shard[0], shard[1]]) as usize;
+            shard.get(2..2 + len).map(Box::from)
+        }).collect();
+
+        self.data.clear();
+        self.parity.clear();
+        Some(recovered)
+    }
+}
+
+/// Datagrams are delivered as soon as they arrive. Lost ones are delivered once enough packets in the group arrive.
+async fn decode(mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+    let mut groups: BTreeMap<u32, Group> = BTreeMap::new();
+    let mut newest: Option<u32> = None;
+
+    while let Some(msg) = mail.recv().await {
+        if msg.len() < HEADER_LEN {
+            eprintln!("fec: malformed packet");
+            continue
+        }
+
+        let group_id = u32::from_be_bytes(msg[..4].try_into().unwrap());
+        let [index, n_data, n_parity]: [u8; 3] = msg[4..HEADER_LEN].try_into().unwrap();
+        let (index, n_data, n_parity) = (index as usize, n_data as usize, n_parity as usize);
+        let payload = &msg[HEADER_LEN..];
+
+        // groups are numbered with wrapping u32, so compare them by the distance to the newest one
+        match newest {
+            Some(x) if (group_id.wrapping_sub(x) as i32) <= 0 => {
+                if x.wrapping_sub(group_id) > GROUP_WINDOW {
+                    continue // too old, we cannot tell if it has been reconstructed
+                }
+            }
+            _ => {
+                newest = Some(group_id);
+                groups.retain(|&x, _| group_id.wrapping_sub(x) <= GROUP_WINDOW);
+            }
+        }
+
+        let group = groups.entry(group_id).or_default();
+        if group.done {
+            continue
+        }
+
+        if n_data == 0 { // data packet
+            if group.layout.is_some_and(|(n_data, _)| index >= n_data) {
+                eprintln!("fec: malformed packet");
+                continue
+            }
+            if group.data.len() <= index {
+                group.data.resize(index + 1, None)
+            }
+            if group.data[index].is_some() {
+                continue // duplicated
+            }
+            group.data[index] = Some(payload.into());
+            if addr.send(payload.into()).await.is_err() {
+                return
+            }
+        } else { // parity packet
+            if index < n_data || index >= n_data + n_parity || group.layout.is_some_and(|x| x != (n_data, n_parity)) || group.data.len() > n_data {
+                eprintln!("fec: malformed packet");
+                continue
+            }
+            group.layout = Some((n_data, n_parity));
+            group.parity.resize(n_parity, None);
+            group.parity[index - n_data] = Some(payload.into());
+        }
+
+        if let Some(recovered) = group.reconstruct() {
+            for msg in recovered {
+                if addr.send(msg).await.is_err() {
+                    return
+                }
+            }
+        }
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
@@ -0,0 +1,248 @@
+use api::serde::Deserialize;
+
+struct Component;
+
+#[derive(Clone, Copy)]
+enum Role { Encoder, Decoder }
+
+#[derive(Clone, Copy)]
+enum Format {
+    Length, // u32 big-endian length prefix
+    Varint, // LEB128 length prefix
+    Line // newline-delimited
+}
+
+struct Actor {
+    format: Format,
+    max_size: usize,
+    role: Role
+}
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            #[serde(default)]
+            format: String,
+
+            max_size: Option<usize>,
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("frame must have exactly 1 output")
+        }
+
+        Box::new(Actor {
+            format: match config.format.to_lowercase().trim() {
+                "" | "length" => Format::Length,
+                "varint" => Format::Varint,
+                "line" => Format::Line,
+                _ => panic!("unknown frame format. Available: length, varint, line")
+            },
+            max_size: config.max_size.unwrap_or(16 << 20),
+            role: match config.function_name {
+                "frame_encode" => Role::Encoder,
+                "frame_decode" => Role::Decoder,
+                _ => unreachable!()
+            }
+        })
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["frame_encode", "frame_decode"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "frame"
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Actor {
+    fn spawn(&'static self, runtime: R, mut metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+
+        // the encoded side is a byte stream, and the decoded side preserves message boundaries
+        metadata.set("datagram".into(), matches!(self.role, Role::Decoder));
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+
+        match self.role {
+            Role::Encoder => {
+                runtime.spawn_task(self.encode(forward_address, mailbox.expect("no mailbox")));
+                if let Some(address) = address { // datagram sources may not accept replies
+                    runtime.spawn_task(self.decode(address, backward_mailbox));
+                }
+            }
+            Role::Decoder => {
+                runtime.spawn_task(self.decode(forward_address, mailbox.expect("no mailbox")));
+                if let Some(address) = address {
+                    runtime.spawn_task(self.encode(address, backward_mailbox));
+                }
+            }
+        }
+    }
+
+    fn spawn_composite(&'static self, runtime: R, _metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        match self.role {
+            Role::Encoder => runtime.spawn_task(self.encode(address.expect("no address"), mailbox.expect("no mailbox"))),
+            Role::Decoder => runtime.spawn_task(self.decode(address.expect("no address"), mailbox.expect("no mailbox")))
+        }
+    }
+}
+
+impl Actor {
+    async fn encode(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        while let Some(msg) = mail.recv().await {
+            let mut buf = Vec::with_capacity(msg.len() + 10);
+
+            match self.format {
+                Format::Length => {
+                    if msg.len() > u32::MAX as usize {
+                        return eprintln!("frame: message too large")
+                    }
+                    buf.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+                    buf.extend_from_slice(&msg);
+                }
+                Format::Varint => {
+                    let mut len = msg.len() as u64;
+                    while len >= 0x80 {
+                        buf.push(len as u8 | 0x80);
+                        len >>= 7;
+                    }
+                    buf.push(len as u8);
+                    buf.extend_from_slice(&msg);
+                }
+                Format::Line => {
+                    // the peer would split it into two messages
+                    if msg.contains(&b'\n') {
+                        return eprintln!("frame: message contains newline")
+                    }
+                    buf.extend_from_slice(&msg);
+                    buf.push(b'\n');
+                }
+            }
+
+            if addr.send(buf.into()).await.is_err() {
+                return
+            }
+        }
+    }
+
+    async fn decode(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut buf: Vec<u8> = vec![];
+        let mut scanned = 0; // the bytes at the start of `buf` that are known to be an incomplete line
+
+        while let Some(msg) = mail.recv().await {
+            buf.extend_from_slice(&msg);
+
+            let mut offset = 0;
+            loop {
+                let (header_len, len) = match self.parse_header(&buf[offset..], scanned) {
+                    Ok(Some(x)) => x,
+                    Ok(None) => { // need more data
+                        scanned = buf.len() - offset;
+                        break
+                    }
+                    Err(e) => return eprintln!("frame: {}", e)
+                };
+                scanned = 0;
+
+                if len > self.max_size {
+                    return eprintln!("frame: message too large")
+                }
+
+                if buf.len() - offset < header_len + len {
+                    break
+                }
+
+                let frame = Box::from(&buf[offset + header_len..offset + header_len + len]);
+                if addr.send(frame).await.is_err() {
+                    return
+                }
+
+                offset += header_len + len;
+                if let Format::Line = self.format {
+                    offset += 1 // the newline
+                }
+            }
+
+            buf.drain(..offset);
+
+            if buf.len() > self.max_size + 10 {
+                return eprintln!("frame: message too large")
+            }
+        }
+    }
+
+    /// returns the length of the header and the length of the message, or None if the data is incomplete. For lines,
+    /// the first `scanned` bytes are known to have no newline.
+    fn parse_header(&self, data: &[u8], scanned: usize) -> Result<Option<(usize, usize)>, &'static str> {
+        match self.format {
+            Format::Length => {
+                if data.len() < 4 {
+                    return Ok(None)
+                }
+                Ok(Some((4, u32::from_be_bytes(data[..4].try_into().unwrap()) as usize)))
+            }
+            Format::Varint => {
+                let mut len: u64 = 0;
+                for (i, byte) in data.iter().enumerate() {
+                    if i >= 10 {
+                        return Err("invalid varint")
+                    }
+                    len |= ((byte & 0x7f) as u64) << (7 * i);
+                    if byte & 0x80 == 0 {
+                        return Ok(Some((i + 1, len as usize)))
+                    }
+                }
+                Ok(None)
+            }
+            Format::Line => Ok(data[scanned..].iter().position(|&x| x == b'\n').map(|i| (0, scanned + i)))
+        }
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actor(format: Format) -> Actor {
+        Actor { format, max_size: 1 << 20, role: Role::Decoder }
+    }
+
+    #[test]
+    fn length() {
+        let actor = actor(Format::Length);
+        assert_eq!(actor.parse_header(&[0, 0, 1], 0), Ok(None));
+        assert_eq!(actor.parse_header(&[0, 0, 1, 2, 0xff], 0), Ok(Some((4, 258))));
+    }
+
+    #[test]
+    fn varint() {
+        let actor = actor(Format::Varint);
+        assert_eq!(actor.parse_header(&[5, 1], 0), Ok(Some((1, 5))));
+        assert_eq!(actor.parse_header(&[0xac, 0x02], 0), Ok(Some((2, 300))));
+        assert_eq!(actor.parse_header(&[0xac], 0), Ok(None));
+        assert!(actor.parse_header(&[0x80; 11], 0).is_err());
+    }
+
+    #[test]
+    fn line() {
+        let actor = actor(Format::Line);
+        assert_eq!(actor.parse_header(b"abc", 0), Ok(None));
+        assert_eq!(actor.parse_header(b"abc\ndef\n", 0), Ok(Some((0, 3))));
+        assert_eq!(actor.parse_header(b"abcdef\n", 3), Ok(Some((0, 6))));
+        assert_eq!(actor.parse_header(b"\n", 0), Ok(Some((0, 0))));
+    }
+}
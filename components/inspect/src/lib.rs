@@ -0,0 +1,163 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use api::{Address, Mailbox};
+use api::serde::Deserialize;
+
+struct Component;
+
+#[derive(Clone, Copy)]
+enum Format { Hex, Text }
+
+struct Actor {
+    format: Format,
+    max_bytes: Option<usize>, // None for unlimited
+    sample: u64,
+    first: Option<u64>,
+    prefix: String, // "inspect" or "inspect(label)"
+    count: AtomicU64 // ids for streams without `stream_id`
+}
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            format: Option<&'a str>,
+            max_bytes: Option<usize>,
+            sample: Option<u64>,
+            first: Option<u64>,
+            label: Option<&'a str>,
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("inspect must have exactly 1 output")
+        }
+
+        let format = match config.format.unwrap_or("hex") {
+            "hex" => Format::Hex,
+            "text" => Format::Text,
+            x => panic!("unknown inspect format: {}", x)
+        };
+
+        if config.sample == Some(0) {
+            panic!("inspect sample must be positive")
+        }
+
+        Box::new(Actor {
+            format,
+            max_bytes: match config.max_bytes.unwrap_or(256) {
+                0 => None,
+                x => Some(x)
+            },
+            sample: config.sample.unwrap_or(1),
+            first: config.first,
+            prefix: match config.label {
+                Some(label) => format!("inspect({})", label),
+                None => "inspect".to_string()
+            },
+            count: 0.into()
+        })
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["inspect"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "inspect"
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Actor {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let id = metadata.get::<u64>("stream_id").copied().unwrap_or_else(|| self.count.fetch_add(1, Ordering::Relaxed));
+        let start = Instant::now();
+
+        let mut line = format!("{}: stream {} open", self.prefix, id);
+        if let Some(origin) = metadata.get::<SocketAddr>("origin_addr") {
+            write!(line, " from {}", origin).unwrap()
+        }
+        match (metadata.get::<String>("destination_addr"), metadata.get::<u16>("destination_port")) {
+            (Some(addr), Some(port)) => write!(line, " to {}:{}", addr, port).unwrap(),
+            (Some(addr), None) => write!(line, " to {}", addr).unwrap(),
+            (None, Some(port)) => write!(line, " to port {}", port).unwrap(),
+            (None, None) => {}
+        }
+        eprintln!("{}", line);
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        runtime.spawn_task(self.pass(id, ">", start, forward_address, mailbox.expect("no mailbox")));
+        if let Some(address) = address { // datagram sources may not accept replies
+            runtime.spawn_task(self.pass(id, "<", start, address, backward_mailbox));
+        }
+    }
+}
+
+impl Actor {
+    /// `direction` is ">" for forward and "<" for backward
+    async fn pass(&self, id: u64, direction: &str, start: Instant, mut addr: impl Address, mut mail: impl Mailbox) {
+        let mut n = 0;
+        while let Some(msg) = mail.recv().await {
+            if n % self.sample == 0 && self.first.is_none_or(|first| n < first) {
+                eprint!("{}", self.dump(id, direction, start, &msg))
+            }
+            n += 1;
+
+            if addr.send(msg).await.is_err() {
+                return
+            }
+        }
+        eprintln!("{}: stream {} {} end after {} messages at +{:.3}ms", self.prefix, id, direction, n, elapsed(start))
+    }
+
+    /// format a message into lines, so it can be printed at once without interleaving with other streams
+    fn dump(&self, id: u64, direction: &str, start: Instant, msg: &[u8]) -> String {
+        let shown = &msg[..self.max_bytes.unwrap_or(msg.len()).min(msg.len())];
+        let mut out = format!("{}: stream {} {} {} bytes at +{:.3}ms", self.prefix, id, direction, msg.len(), elapsed(start));
+
+        match self.format {
+            Format::Hex => {
+                out.push('\n');
+                for (i, line) in shown.chunks(16).enumerate() {
+                    write!(out, "  {:08x} ", i * 16).unwrap();
+                    for j in 0..16 {
+                        if j == 8 {
+                            out.push(' ')
+                        }
+                        match line.get(j) {
+                            Some(x) => write!(out, " {:02x}", x).unwrap(),
+                            None => out.push_str("   ")
+                        }
+                    }
+                    let ascii: String = line.iter().map(|&x| if x.is_ascii_graphic() || x == b' ' { x as char } else { '.' }).collect();
+                    writeln!(out, "  |{}|", ascii).unwrap();
+                }
+            }
+            Format::Text => writeln!(out, ": \"{}\"", shown.escape_ascii()).unwrap()
+        }
+
+        if shown.len() < msg.len() {
+            writeln!(out, "  ... {} more bytes", msg.len() - shown.len()).unwrap()
+        }
+        out
+    }
+}
+
+fn elapsed(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
@@ -0,0 +1,134 @@
+use api::serde::Deserialize;
+
+struct Component;
+
+#[derive(Clone, Copy)]
+enum Role { Encoder, Decoder }
+
+const MAX_BLOCK: usize = 1 << 20; // larger messages are split into multiple blocks
+
+struct Actor {
+    role: Role
+}
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("lz4 must have exactly 1 output")
+        }
+
+        Box::new(Actor {
+            role: match config.function_name {
+                "lz4_deflate" => Role::Encoder,
+                "lz4_inflate" => Role::Decoder,
+                _ => unreachable!()
+            }
+        })
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["lz4_deflate", "lz4_inflate"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "lz4"
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Actor {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        if metadata.get::<bool>("datagram").copied().unwrap_or(false) {
+            eprintln!("WARNING: the lz4 module is not designed for datagrams")
+        }
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        match self.role {
+            Role::Encoder => {
+                runtime.spawn_task(self.deflate(forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(self.inflate(address.expect("no address"), backward_mailbox));
+            }
+            Role::Decoder => {
+                runtime.spawn_task(self.inflate(forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(self.deflate(address.expect("no address"), backward_mailbox));
+            }
+        }
+    }
+
+    fn spawn_composite(&'static self, runtime: R, _metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        match self.role {
+            Role::Encoder => runtime.spawn_task(self.deflate(address.expect("no address"), mailbox.expect("no mailbox"))),
+            Role::Decoder => runtime.spawn_task(self.inflate(address.expect("no address"), mailbox.expect("no mailbox")))
+        }
+    }
+}
+
+impl Actor {
+    /// Each block is compressed independently and sent as [compressed length (u32)][uncompressed length (u32, LE)][data]
+    async fn deflate(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        while let Some(msg) = mail.recv().await {
+            for chunk in msg.chunks(MAX_BLOCK) {
+                let block = lz4_flex::compress_prepend_size(chunk);
+                let mut buf = Vec::with_capacity(4 + block.len());
+                buf.extend_from_slice(&(block.len() as u32).to_be_bytes());
+                buf.extend_from_slice(&block);
+
+                if addr.send(buf.into()).await.is_err() {
+                    return
+                }
+            }
+        }
+    }
+
+    async fn inflate(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let max_compressed = 4 + lz4_flex::block::get_maximum_output_size(MAX_BLOCK);
+        let mut buf: Vec<u8> = vec![];
+
+        while let Some(msg) = mail.recv().await {
+            buf.extend_from_slice(&msg);
+
+            let mut offset = 0;
+            while buf.len() - offset >= 4 {
+                let len = u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+                if len < 4 || len > max_compressed {
+                    return eprintln!("lz4: corrupted data")
+                }
+                if buf.len() - offset < 4 + len {
+                    break
+                }
+
+                let block = &buf[offset + 4..offset + 4 + len];
+                if u32::from_le_bytes(block[..4].try_into().unwrap()) as usize > MAX_BLOCK {
+                    return eprintln!("lz4: corrupted data")
+                }
+                let content = match lz4_flex::decompress_size_prepended(block) {
+                    Ok(x) => x,
+                    Err(_) => return eprintln!("lz4: corrupted data")
+                };
+
+                if addr.send(content.into()).await.is_err() {
+                    return
+                }
+
+                offset += 4 + len;
+            }
+
+            buf.drain(..offset);
+        }
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
@@ -1,13 +1,22 @@
+use std::time::Duration;
+
 use api::serde::Deserialize;
-use miniz_oxide::{deflate::{core::CompressorOxide, stream::deflate}, inflate::stream::{InflateState, inflate}, StreamResult, DataFormat, MZFlush};
+use miniz_oxide::{deflate::{core::CompressorOxide, stream::deflate}, inflate::stream::{InflateState, inflate}, StreamResult, DataFormat, MZFlush, MZError, MZStatus};
 
 struct Component;
 
 #[derive(Clone, Copy)]
 enum Role { Encoder, Decoder }
 
+const BUFFER_SIZE: usize = 65536;
+const PROBE_MIN: usize = 512; // batches smaller than this are too noisy to judge the compressibility
+const STORED_SPAN: usize = 1 << 20; // the number of bytes to send stored before probing again
+
 struct Actor {
     level: u8,
+    adaptive: bool,
+    flush_delay: Option<Duration>,
+    flush_size: usize,
     role: Role
 }
 
@@ -19,6 +28,12 @@ impl<R: api::Runtime> api::Component<R> for Component {
         struct Config<'a> {
             level: Option<u8>,
 
+            #[serde(default)]
+            adaptive: bool,
+
+            flush_delay: Option<u64>, // ms
+            flush_size: Option<usize>,
+
             outputs: Vec<&'a str>,
             function_name: &'a str,
         }
@@ -29,8 +44,16 @@ impl<R: api::Runtime> api::Component<R> for Component {
             panic!("miniz must have exactly 1 output")
         }
 
+        let level = config.level.unwrap_or(1);
+        if level > 10 {
+            panic!("miniz level must be between 0 and 10")
+        }
+
         Box::new(Actor {
-            level: config.level.unwrap_or(1),
+            level,
+            adaptive: config.adaptive,
+            flush_delay: config.flush_delay.map(Duration::from_millis),
+            flush_size: config.flush_size.unwrap_or(BUFFER_SIZE),
             role: match config.function_name {
                 "deflate" => Role::Encoder,
                 "inflate" => Role::Decoder,
@@ -50,10 +73,8 @@ impl<R: api::Runtime> api::Component<R> for Component {
 
 impl<R: api::Runtime> api::Actor<R> for Actor {
     fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
-        if let Some(stream_type) = metadata.get::<String>("stream_type") {
-            if stream_type == "UDP" {
-                eprintln!("WARNING: the aead module is not designed for UDP")
-            }
+        if metadata.get::<bool>("datagram").copied().unwrap_or(false) {
+            eprintln!("WARNING: the miniz module is not designed for datagrams")
         }
 
         let (forward_address, forward_mailbox) = runtime.channel();
@@ -80,47 +101,128 @@ impl<R: api::Runtime> api::Actor<R> for Actor {
 }
 
 impl Actor {
+    /// Each batch ends with a sync flush so the peer can decompress it immediately. Without `flush_delay` a batch is a
+    /// single message; otherwise messages are collected until `flush_delay` passes or `flush_size` bytes are pending.
     async fn deflate(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
         let mut compressor = CompressorOxide::default();
         compressor.set_format_and_level(DataFormat::Raw, self.level);
 
-        let mut buffer = vec![0; 65536];
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut stored: usize = 0; // remaining bytes to send stored in adaptive mode
+        let mut started = false;
         while let Some(msg) = mail.recv().await {
-            if buffer.len() < msg.len() * 2 { // assume that compress data is not twice longer. Need to revisit this later.
-                buffer.resize(msg.len() * 2, 0)
+            started = true;
+            let mut pending = msg.into_vec();
+            let mut closed = false;
+            if let Some(delay) = self.flush_delay {
+                let deadline = tokio::time::Instant::now() + delay;
+                while pending.len() < self.flush_size {
+                    match tokio::time::timeout_at(deadline, mail.recv()).await {
+                        Ok(Some(msg)) => pending.extend_from_slice(&msg),
+                        Ok(None) => { closed = true; break }
+                        Err(_) => break
+                    }
+                }
             }
 
-            let StreamResult { bytes_consumed, bytes_written, status } = deflate(&mut compressor, &msg, &mut buffer, MZFlush::Sync);
-            assert!(status.is_ok());
-            assert_eq!(bytes_consumed, msg.len());
+            let compressed = match compress(&mut compressor, &pending, &mut buffer, MZFlush::Sync) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("miniz: compression failed ({:?})", e);
+                    return
+                }
+            };
+
+            // incompressible data (e.g. already encrypted) are sent in stored blocks for a while to save CPU
+            if self.adaptive {
+                if stored > 0 {
+                    stored = stored.saturating_sub(pending.len());
+                    if stored == 0 {
+                        compressor.set_compression_level_raw(self.level)
+                    }
+                } else if self.level > 0 && pending.len() >= PROBE_MIN && compressed.len() >= pending.len() - pending.len() / 32 {
+                    compressor.set_compression_level_raw(0);
+                    stored = STORED_SPAN
+                }
+            }
 
-            if addr.send(buffer[..bytes_written].to_vec().into()).await.is_err() {
+            if addr.send(compressed.into()).await.is_err() {
                 return
             }
+            if closed {
+                break
+            }
+        }
+
+        // mark the end so the peer can tell it from a truncated stream. Directions that never carried data stay empty.
+        if !started {
+            return
+        }
+        match compress(&mut compressor, &[], &mut buffer, MZFlush::Finish) {
+            Ok(end) => { let _ = addr.send(end.into()).await; }
+            Err(e) => eprintln!("miniz: compression failed ({:?})", e)
         }
     }
 
     async fn inflate(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
         let mut decompressor = InflateState::new_boxed(DataFormat::Raw);
 
-        let mut buffer = vec![0; 65536];
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut started = false;
         while let Some(msg) = mail.recv().await {
+            started = true;
             let mut offset = 0;
 
             loop {
-                let StreamResult { bytes_consumed, bytes_written, status: _ } = inflate(&mut decompressor, &msg[offset..], &mut buffer, MZFlush::Sync);
+                let StreamResult { bytes_consumed, bytes_written, status } = inflate(&mut decompressor, &msg[offset..], &mut buffer, MZFlush::None);
+                offset += bytes_consumed;
 
-                if addr.send(buffer[..bytes_written].to_vec().into()).await.is_err() {
+                if bytes_written > 0 && addr.send(buffer[..bytes_written].to_vec().into()).await.is_err() {
                     return
                 }
 
-                offset += bytes_consumed;
-
-                if offset >= msg.len() {
-                    break;
+                match status {
+                    Ok(MZStatus::StreamEnd) => {
+                        if offset < msg.len() {
+                            eprintln!("miniz: trailing data after the end of the stream")
+                        }
+                        return
+                    }
+                    Ok(_) if bytes_consumed == 0 && bytes_written == 0 => break, // no progress, wait for more input
+                    Ok(_) if offset >= msg.len() && bytes_written < buffer.len() => break,
+                    Ok(_) => continue,
+                    Err(MZError::Buf) => break,
+                    Err(e) => {
+                        eprintln!("miniz: corrupted data ({:?})", e);
+                        return
+                    }
                 }
             }
         }
+
+        if started {
+            eprintln!("miniz: stream ended without the end mark, it might be truncated")
+        }
+    }
+}
+
+/// compress `input` and flush (sync, or finish at the end of the stream), using `buffer` as the scratch space
+fn compress(compressor: &mut CompressorOxide, input: &[u8], buffer: &mut [u8], flush: MZFlush) -> Result<Vec<u8>, MZError> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 64);
+    let mut offset = 0;
+
+    loop {
+        let StreamResult { bytes_consumed, bytes_written, status } = deflate(compressor, &input[offset..], buffer, flush);
+        offset += bytes_consumed;
+        output.extend_from_slice(&buffer[..bytes_written]);
+
+        match status {
+            // the flush is complete once all input is consumed without filling the buffer
+            Ok(_) if offset >= input.len() && bytes_written < buffer.len() => return Ok(output),
+            Ok(_) => continue,
+            Err(MZError::Buf) if offset >= input.len() => return Ok(output), // nothing left to flush
+            Err(e) => return Err(e)
+        }
     }
 }
 
@@ -0,0 +1,53 @@
+use std::sync::{Arc, Mutex, atomic::{AtomicU32, Ordering}};
+
+use crate::session::{Session, encode_destination};
+
+pub struct Client<R: api::Runtime> {
+    window: u32,
+    session: Mutex<Option<Arc<Session<R::Address>>>>,
+    next_id: AtomicU32
+}
+
+impl<R: api::Runtime> Client<R> {
+    pub fn new(window: u32) -> Self {
+        Self { window, session: Default::default(), next_id: 0.into() }
+    }
+
+    /// get the current carrier, or establish a new one if there is none or it has been closed.
+    fn session(&'static self, runtime: &R, metadata: &api::MetaData) -> Arc<Session<R::Address>> {
+        let mut session = self.session.lock().unwrap();
+        if let Some(session) = &*session {
+            if session.is_alive() {
+                return session.clone()
+            }
+        }
+
+        // the carrier is not any particular stream, so it does not inherit the metadata of the stream that creates it
+        let mut carrier_metadata = api::MetaData::default();
+        if let Some(stream_type) = metadata.get::<String>("stream_type") {
+            carrier_metadata.set("stream_type".into(), stream_type.clone());
+        }
+        carrier_metadata.set("datagram".into(), false); // the carrier is a byte stream of frames
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, carrier_metadata, backward_address, forward_mailbox);
+
+        let new_session = Session::new(forward_address, self.window);
+        runtime.spawn_task(new_session.clone().run(backward_mailbox, |_, _, _| false)); // the server never opens streams
+
+        *session = Some(new_session.clone());
+        new_session
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Client<R> {
+    fn spawn(&'static self, runtime: R, mut metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let addr = metadata.take::<String>("destination_addr").map(|x| *x);
+        let port = metadata.take::<u16>("destination_port").map(|x| *x);
+
+        let session = self.session(&runtime, &metadata);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        session.attach(&runtime, id, Some(encode_destination(addr, port)), address.expect("no address"), mailbox.expect("no mailbox"));
+    }
+}
@@ -0,0 +1,50 @@
+use api::serde::Deserialize;
+
+mod session;
+mod client;
+mod server;
+
+struct Component;
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            window: Option<u32>,
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("mux must have exactly 1 output")
+        }
+
+        let window = config.window.unwrap_or(session::DEFAULT_WINDOW);
+        if window < session::INITIAL_WINDOW {
+            panic!("mux window must be at least {} bytes", session::INITIAL_WINDOW)
+        }
+
+        match config.function_name {
+            "mux_client" => Box::new(client::Client::<R>::new(window)),
+            "mux_server" => Box::new(server::Server::new(window)),
+            _ => unreachable!()
+        }
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["mux_client", "mux_server"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "mux"
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
@@ -0,0 +1,43 @@
+use crate::session::{Session, decode_destination};
+
+pub struct Server {
+    window: u32
+}
+
+impl Server {
+    pub fn new(window: u32) -> Self {
+        Self { window }
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Server {
+    fn spawn(&'static self, runtime: R, mut metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        // the status of a stream does not concern the carrier
+        metadata.take::<R::StatusReporter>("status_reporter");
+
+        let session = Session::new(address.expect("no address"), self.window);
+        let mailbox = mailbox.expect("no mailbox");
+
+        runtime.spawn_task_with_runtime(move |runtime| session.run(mailbox, move |session, id, payload| {
+            let (addr, port) = match decode_destination(payload) {
+                Some(x) => x,
+                None => return false
+            };
+
+            // streams inherit the metadata of the carrier (e.g. origin_addr)
+            let mut metadata = metadata.clone();
+            if let Some(addr) = addr {
+                metadata.set("destination_addr".into(), addr);
+            }
+            if let Some(port) = port {
+                metadata.set("destination_port".into(), port);
+            }
+
+            let (forward_address, forward_mailbox) = runtime.channel();
+            let (backward_address, backward_mailbox) = runtime.channel();
+            runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+            session.attach(&runtime, id, None, forward_address, backward_mailbox);
+            true
+        }));
+    }
+}
@@ -0,0 +1,263 @@
+//! The framing protocol shared by the client and the server. See readme.md for the wire format.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+
+use api::{Address, Mailbox};
+use tokio::sync::{mpsc, Semaphore};
+
+pub const INITIAL_WINDOW: u32 = 65536;
+pub const DEFAULT_WINDOW: u32 = 262144;
+
+const MAX_PAYLOAD: usize = 16384;
+const HEADER_LEN: usize = 7; // kind (u8) + stream id (u32) + payload length (u16)
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum Kind { Open, Data, Fin, Rst, Window }
+
+impl Kind {
+    fn from_u8(x: u8) -> Option<Self> {
+        match x {
+            0 => Some(Kind::Open),
+            1 => Some(Kind::Data),
+            2 => Some(Kind::Fin),
+            3 => Some(Kind::Rst),
+            4 => Some(Kind::Window),
+            _ => None
+        }
+    }
+}
+
+fn frame(kind: Kind, id: u32, payload: &[u8]) -> Box<[u8]> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+    buf.push(kind as u8);
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf.into()
+}
+
+/// encode the destination of a stream as the payload of an OPEN frame
+pub fn encode_destination(addr: Option<String>, port: Option<u16>) -> Box<[u8]> {
+    if addr.is_none() && port.is_none() {
+        return Box::new([])
+    }
+
+    let mut buf = port.unwrap_or(0).to_be_bytes().to_vec();
+    buf.extend_from_slice(addr.unwrap_or_default().as_bytes());
+    buf.into()
+}
+
+/// decode the payload of an OPEN frame. Returns None if the payload is malformed.
+#[allow(clippy::type_complexity)]
+pub fn decode_destination(payload: &[u8]) -> Option<(Option<String>, Option<u16>)> {
+    if payload.is_empty() {
+        return Some((None, None))
+    }
+
+    if payload.len() < 2 {
+        return None
+    }
+
+    let port = u16::from_be_bytes(payload[..2].try_into().unwrap());
+    let addr = std::str::from_utf8(&payload[2..]).ok()?;
+
+    Some((
+        Some(addr.to_string()).filter(|x| !x.is_empty()),
+        Some(port).filter(|&x| x != 0)
+    ))
+}
+
+struct Stream {
+    deliver: Option<mpsc::UnboundedSender<Box<[u8]>>>, // None after the peer sent FIN
+    credit: Arc<Semaphore>, // bytes we are allowed to send
+    local_fin: bool
+}
+
+/// A carrier and the logical streams on it.
+pub struct Session<A: Address> {
+    carrier: A,
+    streams: Mutex<BTreeMap<u32, Stream>>,
+    window: u32,
+    alive: AtomicBool
+}
+
+impl<A: Address> Session<A> {
+    pub fn new(carrier: A, window: u32) -> Arc<Self> {
+        Arc::new(Session { carrier, streams: Default::default(), window, alive: true.into() })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    /// attach a logical stream to the carrier. Data from the peer are sent to `address` and messages in `mailbox` are
+    /// sent to the peer. If `open` is given, an OPEN frame with it as the payload is sent first.
+    pub fn attach(self: &Arc<Self>, runtime: &impl api::Runtime, id: u32, open: Option<Box<[u8]>>, address: impl Address, mailbox: impl Mailbox) {
+        let (deliver, delivery) = mpsc::unbounded_channel();
+        let credit = Arc::new(Semaphore::new(INITIAL_WINDOW as _));
+        self.streams.lock().unwrap().insert(id, Stream { deliver: Some(deliver), credit: credit.clone(), local_fin: false });
+
+        runtime.spawn_task(self.clone().send_stream(id, open, credit, mailbox));
+        runtime.spawn_task(self.clone().recv_stream(id, delivery, address));
+    }
+
+    async fn send_stream(self: Arc<Self>, id: u32, open: Option<Box<[u8]>>, credit: Arc<Semaphore>, mut mailbox: impl Mailbox) {
+        let mut carrier = self.carrier.clone();
+
+        if let Some(payload) = open {
+            if carrier.send(frame(Kind::Open, id, &payload)).await.is_err() {
+                return self.close()
+            }
+        }
+
+        if self.window > INITIAL_WINDOW {
+            let increment = self.window - INITIAL_WINDOW;
+            if carrier.send(frame(Kind::Window, id, &increment.to_be_bytes())).await.is_err() {
+                return self.close()
+            }
+        }
+
+        while let Some(msg) = mailbox.recv().await {
+            for chunk in msg.chunks(MAX_PAYLOAD) {
+                match credit.acquire_many(chunk.len() as _).await {
+                    Ok(permit) => permit.forget(),
+                    Err(_) => return // the stream is reset or the carrier is closed
+                }
+
+                if carrier.send(frame(Kind::Data, id, chunk)).await.is_err() {
+                    return self.close()
+                }
+            }
+        }
+
+        if carrier.send(frame(Kind::Fin, id, &[])).await.is_err() {
+            return self.close()
+        }
+
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(stream) = streams.get_mut(&id) {
+            if stream.deliver.is_none() {
+                streams.remove(&id);
+            } else {
+                stream.local_fin = true
+            }
+        }
+    }
+
+    async fn recv_stream(self: Arc<Self>, id: u32, mut delivery: mpsc::UnboundedReceiver<Box<[u8]>>, mut address: impl Address) {
+        let mut carrier = self.carrier.clone();
+        let mut consumed = 0;
+
+        while let Some(msg) = delivery.recv().await {
+            let len = msg.len() as u32;
+            if address.send(msg).await.is_err() {
+                // the local stream is gone, tell the peer to stop sending
+                self.reset(id);
+                let _ = carrier.send(frame(Kind::Rst, id, &[])).await;
+                return
+            }
+
+            // return the credits in batches, but never hold them while idle
+            consumed += len;
+            if consumed >= self.window / 4 || delivery.is_empty() {
+                if carrier.send(frame(Kind::Window, id, &consumed.to_be_bytes())).await.is_err() {
+                    return self.close()
+                }
+                consumed = 0
+            }
+        }
+    }
+
+    /// read frames from the carrier and dispatch them until the carrier closes. `on_open` is called for each OPEN frame
+    /// and is expected to attach a new stream. It returns false if the stream cannot be opened.
+    pub async fn run(self: Arc<Self>, mut mailbox: impl Mailbox, mut on_open: impl FnMut(&Arc<Self>, u32, &[u8]) -> bool) {
+        let mut buf: Vec<u8> = vec![];
+
+        'carrier: while let Some(msg) = mailbox.recv().await {
+            buf.extend_from_slice(&msg);
+
+            let mut offset = 0;
+            while buf.len() - offset >= HEADER_LEN {
+                let header = &buf[offset..offset + HEADER_LEN];
+                let len = u16::from_be_bytes(header[5..7].try_into().unwrap()) as usize;
+                if buf.len() - offset < HEADER_LEN + len {
+                    break
+                }
+
+                let id = u32::from_be_bytes(header[1..5].try_into().unwrap());
+                let payload = &buf[offset + HEADER_LEN..offset + HEADER_LEN + len];
+                if !self.dispatch(header[0], id, payload, &mut on_open) {
+                    eprintln!("mux: protocol error");
+                    break 'carrier
+                }
+
+                offset += HEADER_LEN + len;
+            }
+
+            buf.drain(..offset);
+        }
+
+        self.close()
+    }
+
+    fn dispatch(self: &Arc<Self>, kind: u8, id: u32, payload: &[u8], on_open: &mut impl FnMut(&Arc<Self>, u32, &[u8]) -> bool) -> bool {
+        match Kind::from_u8(kind) {
+            Some(Kind::Open) => {
+                if self.streams.lock().unwrap().contains_key(&id) {
+                    return false
+                }
+                on_open(self, id, payload)
+            }
+            Some(Kind::Data) => {
+                // data for unknown streams can be in-flight frames of a reset stream
+                if let Some(Stream { deliver: Some(deliver), .. }) = self.streams.lock().unwrap().get(&id) {
+                    let _ = deliver.send(payload.into());
+                }
+                true
+            }
+            Some(Kind::Fin) => {
+                let mut streams = self.streams.lock().unwrap();
+                if let Some(stream) = streams.get_mut(&id) {
+                    if stream.local_fin {
+                        streams.remove(&id);
+                    } else {
+                        stream.deliver = None
+                    }
+                }
+                true
+            }
+            Some(Kind::Rst) => {
+                self.reset(id);
+                true
+            }
+            Some(Kind::Window) => {
+                let increment = match payload.try_into() {
+                    Ok(x) => u32::from_be_bytes(x),
+                    Err(_) => return false
+                };
+                if let Some(stream) = self.streams.lock().unwrap().get(&id) {
+                    stream.credit.add_permits(increment as _)
+                }
+                true
+            }
+            None => false
+        }
+    }
+
+    /// drop a stream locally in both directions
+    fn reset(&self, id: u32) {
+        if let Some(stream) = self.streams.lock().unwrap().remove(&id) {
+            stream.credit.close()
+        }
+    }
+
+    /// close the carrier and all streams on it
+    fn close(&self) {
+        self.alive.store(false, Ordering::Relaxed);
+        for (_, stream) in std::mem::take(&mut *self.streams.lock().unwrap()) {
+            stream.credit.close()
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::{Arc, Mutex};
+
+use api::Address;
+
+use crate::{PROLOGUE, NK, IK, MAX_MESSAGE, Reader, frame, seal, open};
+
+/// The initiator. It uses the IK pattern if it has a static key, otherwise NK.
+pub struct Client {
+    pub key: Option<Box<[u8]>>,
+    pub remote_key: Box<[u8]>
+}
+
+impl<R: api::Runtime> api::Actor<R> for Client {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let (mut forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+
+        let address = address.expect("no address");
+        let mailbox = mailbox.expect("no mailbox");
+
+        runtime.spawn_task_with_runtime(move |runtime| async move {
+            let builder = snow::Builder::new(if self.key.is_some() { IK } else { NK }.parse().unwrap())
+                .prologue(PROLOGUE)
+                .remote_public_key(&self.remote_key);
+            let mut handshake = match &self.key {
+                Some(key) => builder.local_private_key(key).build_initiator(),
+                None => builder.build_initiator()
+            }.unwrap();
+
+            let mut buf = vec![0; MAX_MESSAGE];
+            let len = handshake.write_message(&[], &mut buf).unwrap();
+            if forward_address.send(frame(&buf[..len])).await.is_err() {
+                return
+            }
+
+            let mut reader = Reader::new(backward_mailbox);
+            let msg = match reader.next().await {
+                Some(msg) => msg,
+                None => return eprintln!("noise: the server closed the connection during the handshake")
+            };
+            if handshake.read_message(&msg, &mut buf).is_err() {
+                return eprintln!("noise: handshake failed")
+            }
+
+            let transport = Arc::new(Mutex::new(handshake.into_transport_mode().unwrap()));
+            runtime.spawn_task(open(transport.clone(), address, reader));
+            seal(transport, forward_address, mailbox).await
+        });
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::{Arc, Mutex};
+
+use api::serde::Deserialize;
+use base64::Engine;
+use snow::TransportState;
+
+mod client;
+mod server;
+
+const PROLOGUE: &[u8] = b"sopipe noise";
+const NK: &str = "Noise_NK_25519_ChaChaPoly_BLAKE2s";
+const IK: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+
+const MAX_MESSAGE: usize = u16::MAX as usize; // noise messages are at most 65535 bytes
+const TAG_LEN: usize = 16;
+
+struct Component;
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            key: Option<&'a str>,
+            remote_key: Option<&'a str>,
+            authorized: Option<&'a str>, // comma-separated public keys
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("noise must have exactly 1 output")
+        }
+
+        match config.function_name {
+            "noise_client" => Box::new(client::Client {
+                key: config.key.map(decode_key),
+                remote_key: decode_key(config.remote_key.expect("noise_client requires remote_key"))
+            }),
+            "noise_server" => Box::new(server::Server {
+                key: decode_key(config.key.expect("noise_server requires key")),
+                authorized: config.authorized.map(|x| x.split(',').map(|x| decode_key(x.trim())).collect())
+            }),
+            _ => unreachable!()
+        }
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["noise_client", "noise_server"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "noise"
+    }
+}
+
+fn decode_key(key: &str) -> Box<[u8]> {
+    match base64::engine::general_purpose::STANDARD.decode(key) {
+        Ok(key) if key.len() == 32 => key.into(),
+        _ => panic!("noise keys must be 32 bytes in base64")
+    }
+}
+
+/// generate a key pair in base64, returned as (private key, public key)
+pub fn generate_keypair() -> (String, String) {
+    let keypair = snow::Builder::new(NK.parse().unwrap()).generate_keypair().unwrap();
+    let engine = base64::engine::general_purpose::STANDARD;
+    (engine.encode(keypair.private), engine.encode(keypair.public))
+}
+
+/// prefix a noise message with its length
+fn frame(payload: &[u8]) -> Box<[u8]> {
+    let mut buf = Vec::with_capacity(2 + payload.len());
+    buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf.into()
+}
+
+/// reads length-prefixed noise messages from a mailbox
+struct Reader<M: api::Mailbox> {
+    mailbox: M,
+    buf: Vec<u8>
+}
+
+impl<M: api::Mailbox> Reader<M> {
+    fn new(mailbox: M) -> Self {
+        Reader { mailbox, buf: vec![] }
+    }
+
+    async fn next(&mut self) -> Option<Box<[u8]>> {
+        loop {
+            if self.buf.len() >= 2 {
+                let len = u16::from_be_bytes(self.buf[..2].try_into().unwrap()) as usize;
+                if self.buf.len() >= 2 + len {
+                    let msg = Box::from(&self.buf[2..2 + len]);
+                    self.buf.drain(..2 + len);
+                    return Some(msg)
+                }
+            }
+
+            self.buf.extend_from_slice(&self.mailbox.recv().await?)
+        }
+    }
+}
+
+async fn seal(transport: Arc<Mutex<TransportState>>, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+    while let Some(msg) = mail.recv().await {
+        for chunk in msg.chunks(MAX_MESSAGE - TAG_LEN) {
+            let mut buf = vec![0; 2 + chunk.len() + TAG_LEN];
+            let len = match transport.lock().unwrap().write_message(chunk, &mut buf[2..]) {
+                Ok(len) => len,
+                Err(e) => return eprintln!("noise: {}", e)
+            };
+            buf[..2].copy_from_slice(&(len as u16).to_be_bytes());
+            buf.truncate(2 + len);
+
+            if addr.send(buf.into()).await.is_err() {
+                return
+            }
+        }
+    }
+}
+
+async fn open(transport: Arc<Mutex<TransportState>>, mut addr: impl api::Address, mut reader: Reader<impl api::Mailbox>) {
+    while let Some(msg) = reader.next().await {
+        let mut buf = vec![0; msg.len()];
+        let len = match transport.lock().unwrap().read_message(&msg, &mut buf) {
+            Ok(len) => len,
+            Err(_) => return eprintln!("noise: decryption failed")
+        };
+        buf.truncate(len);
+
+        if addr.send(buf.into()).await.is_err() {
+            return
+        }
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex};
+
+use api::Address;
+
+use crate::{PROLOGUE, NK, IK, MAX_MESSAGE, Reader, frame, seal, open};
+
+const NK_FIRST_LEN: usize = 48; // ephemeral key + tag of the empty payload
+const IK_FIRST_LEN: usize = 96; // ephemeral key + encrypted static key + tag of the empty payload
+
+/// The responder. It requires the IK pattern and checks the client key if `authorized` is given, otherwise NK.
+pub struct Server {
+    pub key: Box<[u8]>,
+    pub authorized: Option<Vec<Box<[u8]>>>
+}
+
+impl<R: api::Runtime> api::Actor<R> for Server {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let mut address = address.expect("no address");
+        let mailbox = mailbox.expect("no mailbox");
+
+        runtime.spawn_task_with_runtime(move |runtime| async move {
+            let mut handshake = snow::Builder::new(if self.authorized.is_some() { IK } else { NK }.parse().unwrap())
+                .prologue(PROLOGUE)
+                .local_private_key(&self.key)
+                .build_responder().unwrap();
+
+            let mut reader = Reader::new(mailbox);
+            let msg = match reader.next().await {
+                Some(msg) => msg,
+                None => return
+            };
+
+            let mut buf = vec![0; MAX_MESSAGE];
+            if handshake.read_message(&msg, &mut buf).is_err() {
+                // a mismatched pattern is a common mistake, and can be told from the length of the first message
+                let hint = match (self.authorized.is_some(), msg.len()) {
+                    (false, IK_FIRST_LEN) => " (the client has `key`, which needs `authorized` on the server)",
+                    (true, NK_FIRST_LEN) => " (the client has no `key`, which `authorized` requires)",
+                    _ => ""
+                };
+                if let Some(origin) = metadata.get::<std::net::SocketAddr>("origin_addr") {
+                    eprintln!("noise: handshake failed from {}{}", origin, hint)
+                } else {
+                    eprintln!("noise: handshake failed{}", hint)
+                }
+                return
+            }
+
+            if let Some(authorized) = &self.authorized {
+                let remote_key = handshake.get_remote_static().unwrap();
+                if !authorized.iter().any(|x| x[..] == *remote_key) {
+                    if let Some(origin) = metadata.get::<std::net::SocketAddr>("origin_addr") {
+                        eprintln!("noise: unauthorized client from {}", origin)
+                    } else {
+                        eprintln!("noise: unauthorized client")
+                    }
+                    return
+                }
+            }
+
+            let len = handshake.write_message(&[], &mut buf).unwrap();
+            if address.send(frame(&buf[..len])).await.is_err() {
+                return
+            }
+
+            let (forward_address, forward_mailbox) = runtime.channel();
+            let (backward_address, backward_mailbox) = runtime.channel();
+            runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+
+            let transport = Arc::new(Mutex::new(handshake.into_transport_mode().unwrap()));
+            runtime.spawn_task(seal(transport.clone(), address, backward_mailbox));
+            open(transport, forward_address, reader).await
+        });
+    }
+}
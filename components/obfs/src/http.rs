@@ -0,0 +1,317 @@
+//! Disguise the stream as a WebSocket connection. After the HTTP/1.1 upgrade request and response, data are sent in
+//! WebSocket binary frames (RFC 6455), masked from the client to the server as the protocol requires.
+
+use api::Address;
+use base64::Engine;
+use rand::Rng;
+
+use crate::{reject, warn_datagram};
+
+const MAX_HEAD: usize = 8192;
+const MAX_FRAME: usize = 65536; // the maximum payload of the frames we send. Larger messages are split.
+const MAX_RECEIVE: usize = 1 << 24; // the maximum payload of the frames we accept
+
+const OPCODE_CONTINUATION: u8 = 0;
+const OPCODE_TEXT: u8 = 1;
+const OPCODE_BINARY: u8 = 2;
+const OPCODE_CLOSE: u8 = 8;
+const OPCODE_PING: u8 = 9;
+const OPCODE_PONG: u8 = 10;
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36";
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub struct Client {
+    pub host: &'static str,
+    pub path: &'static str
+}
+
+impl<R: api::Runtime> api::Actor<R> for Client {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        warn_datagram(&metadata, "http_obfs_client");
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        runtime.spawn_task(self.request(forward_address, mailbox.expect("no mailbox")));
+        runtime.spawn_task(response(address.expect("no address"), backward_mailbox));
+    }
+}
+
+impl Client {
+    /// send the request before any data, so it also works for protocols where the server speaks first
+    async fn request(&self, mut addr: impl api::Address, mail: impl api::Mailbox) {
+        let key = base64::engine::general_purpose::STANDARD.encode(rand::thread_rng().gen::<[u8; 16]>());
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            self.path, self.host, USER_AGENT, key
+        );
+
+        if addr.send(request.into_bytes().into()).await.is_err() {
+            return
+        }
+
+        seal(addr, mail, true).await
+    }
+}
+
+async fn response(addr: impl api::Address, mut mail: impl api::Mailbox) {
+    let (head, rest) = match read_head(&mut mail).await {
+        Ok(x) => x,
+        Err(Some(reason)) => return eprintln!("http_obfs_client: {}", reason),
+        Err(None) => return
+    };
+
+    if !head.starts_with("HTTP/1.1 101 ") {
+        return eprintln!("http_obfs_client: unexpected response: {}", head.lines().next().unwrap_or_default())
+    }
+
+    open("http_obfs_client", addr, Reader { mailbox: mail, buf: rest }).await
+}
+
+/// Checks the request and replies like nginx. Requests that do not match `host` and `path` (if given) get a 404 page.
+pub struct Server {
+    pub host: Option<&'static str>,
+    pub path: Option<&'static str>
+}
+
+impl<R: api::Runtime> api::Actor<R> for Server {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        warn_datagram(&metadata, "http_obfs_server");
+
+        let mut address = address.expect("no address");
+        let mut mailbox = mailbox.expect("no mailbox");
+
+        runtime.spawn_task_with_runtime(move |runtime| async move {
+            let (head, rest) = match read_head(&mut mailbox).await {
+                Ok(x) => x,
+                Err(Some(reason)) => return reject(&metadata, "http_obfs_server", reason),
+                Err(None) => return
+            };
+
+            let key = match self.check(&head) {
+                Ok(key) => key,
+                Err(reason) => {
+                    reject(&metadata, "http_obfs_server", reason);
+                    let _ = address.send(not_found().into_bytes().into()).await;
+                    return
+                }
+            };
+
+            let accept = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, format!("{}{}", key, WEBSOCKET_GUID).as_bytes());
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nServer: nginx\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                base64::engine::general_purpose::STANDARD.encode(accept)
+            );
+            if address.send(response.into_bytes().into()).await.is_err() {
+                return
+            }
+
+            let (forward_address, forward_mailbox) = runtime.channel();
+            let (backward_address, backward_mailbox) = runtime.channel();
+            runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+            runtime.spawn_task(seal(address, backward_mailbox, false));
+            open("http_obfs_server", forward_address, Reader { mailbox, buf: rest }).await
+        });
+    }
+}
+
+impl Server {
+    /// check the request head and return the WebSocket key
+    fn check<'a>(&self, head: &'a str) -> Result<&'a str, &'static str> {
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        if request_line.next() != Some("GET") {
+            return Err("unexpected method")
+        }
+        let path = request_line.next().ok_or("malformed request")?;
+        if self.path.is_some_and(|x| x != path) {
+            return Err("unexpected path")
+        }
+
+        let mut host = None;
+        let mut key = None;
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or("malformed header")?;
+            match &name.to_ascii_lowercase()[..] {
+                "host" => host = Some(value.trim()),
+                "sec-websocket-key" => key = Some(value.trim()),
+                _ => {}
+            }
+        }
+
+        if self.host.is_some_and(|x| Some(x) != host) {
+            return Err("unexpected host")
+        }
+
+        key.ok_or("not a websocket request")
+    }
+}
+
+fn not_found() -> String {
+    let body = "<html>\r\n<head><title>404 Not Found</title></head>\r\n<body>\r\n<center><h1>404 Not Found</h1></center>\r\n<hr><center>nginx</center>\r\n</body>\r\n</html>\r\n";
+    format!("HTTP/1.1 404 Not Found\r\nServer: nginx\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+}
+
+/// read until the end of the HTTP head. Returns the head without the trailing empty line, and the data after it.
+/// The error is None if the stream closed before the head completes.
+async fn read_head(mail: &mut impl api::Mailbox) -> Result<(String, Vec<u8>), Option<&'static str>> {
+    let mut buf = vec![];
+
+    loop {
+        buf.extend_from_slice(&mail.recv().await.ok_or(None)?);
+
+        if let Some(end) = buf.windows(4).position(|x| x == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            buf.truncate(end);
+            let head = String::from_utf8(buf).map_err(|_| Some("malformed head"))?;
+            return Ok((head, rest))
+        }
+
+        if buf.len() > MAX_HEAD {
+            return Err(Some("head too large"))
+        }
+    }
+}
+
+/// a single unfragmented WebSocket frame. Clients must mask their frames with a random key.
+fn frame(opcode: u8, payload: &[u8], masked: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(14 + payload.len());
+    buf.push(0x80 | opcode); // FIN
+    let mask_bit = if masked { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => buf.push(mask_bit | len as u8),
+        len @ 126..=0xffff => {
+            buf.push(mask_bit | 126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes())
+        }
+        len => {
+            buf.push(mask_bit | 127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes())
+        }
+    }
+
+    if masked {
+        let key: [u8; 4] = rand::thread_rng().gen();
+        buf.extend_from_slice(&key);
+        buf.extend(payload.iter().enumerate().map(|(i, x)| x ^ key[i % 4]));
+    } else {
+        buf.extend_from_slice(payload);
+    }
+    buf
+}
+
+struct Reader<M: api::Mailbox> {
+    mailbox: M,
+    buf: Vec<u8>
+}
+
+impl<M: api::Mailbox> Reader<M> {
+    /// returns the opcode and the unmasked payload of the next frame. The error is None if the stream closed.
+    async fn next(&mut self) -> Result<(u8, Box<[u8]>), Option<&'static str>> {
+        loop {
+            if let Some((header_len, len, mask)) = parse_frame_header(&self.buf)? {
+                if self.buf.len() >= header_len + len {
+                    let opcode = self.buf[0] & 0x0f;
+                    let mut payload: Box<[u8]> = self.buf[header_len..header_len + len].into();
+                    if let Some(key) = mask {
+                        payload.iter_mut().enumerate().for_each(|(i, x)| *x ^= key[i % 4]);
+                    }
+                    self.buf.drain(..header_len + len);
+                    return Ok((opcode, payload))
+                }
+            }
+
+            self.buf.extend_from_slice(&self.mailbox.recv().await.ok_or(None)?)
+        }
+    }
+}
+
+/// returns the length of the header, the length of the payload and the masking key, or None if incomplete
+#[allow(clippy::type_complexity)]
+fn parse_frame_header(buf: &[u8]) -> Result<Option<(usize, usize, Option<[u8; 4]>)>, Option<&'static str>> {
+    if buf.len() < 2 {
+        return Ok(None)
+    }
+
+    let (mut header_len, len) = match buf[1] & 0x7f {
+        126 if buf.len() >= 4 => (4, u16::from_be_bytes(buf[2..4].try_into().unwrap()) as u64),
+        127 if buf.len() >= 10 => (10, u64::from_be_bytes(buf[2..10].try_into().unwrap())),
+        126 | 127 => return Ok(None),
+        len => (2, len as u64)
+    };
+    if len > MAX_RECEIVE as u64 {
+        return Err(Some("frame too large"))
+    }
+
+    let mask = if buf[1] & 0x80 != 0 {
+        if buf.len() < header_len + 4 {
+            return Ok(None)
+        }
+        header_len += 4;
+        Some(buf[header_len - 4..header_len].try_into().unwrap())
+    } else {
+        None
+    };
+
+    Ok(Some((header_len, len as usize, mask)))
+}
+
+async fn seal(mut addr: impl api::Address, mut mail: impl api::Mailbox, masked: bool) {
+    while let Some(msg) = mail.recv().await {
+        for chunk in msg.chunks(MAX_FRAME) {
+            if addr.send(frame(OPCODE_BINARY, chunk, masked).into()).await.is_err() {
+                return
+            }
+        }
+    }
+}
+
+/// deliver the payload of data frames. A close frame ends the direction.
+async fn open(name: &str, mut addr: impl api::Address, mut reader: Reader<impl api::Mailbox>) {
+    loop {
+        match reader.next().await {
+            Ok((OPCODE_BINARY | OPCODE_TEXT | OPCODE_CONTINUATION, payload)) => {
+                if !payload.is_empty() && addr.send(payload).await.is_err() {
+                    return
+                }
+            }
+            Ok((OPCODE_PING | OPCODE_PONG, _)) => {}
+            Ok((OPCODE_CLOSE, _)) => return,
+            Ok(_) => return eprintln!("{}: unexpected frame", name),
+            Err(Some(reason)) => return eprintln!("{}: {}", name, reason),
+            Err(None) => return
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_lengths() {
+        for (len, header_len) in [(0, 2), (125, 2), (126, 4), (65535, 4), (65536, 10)] {
+            let payload = vec![7; len];
+            let buf = frame(OPCODE_BINARY, &payload, false);
+            assert_eq!(parse_frame_header(&buf).unwrap(), Some((header_len, len, None)));
+            assert_eq!(&buf[header_len..], &payload[..]);
+            assert_eq!(parse_frame_header(&buf[..header_len - 1]).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn masking() {
+        let buf = frame(OPCODE_BINARY, b"hello", true);
+        let (header_len, len, mask) = parse_frame_header(&buf).unwrap().unwrap();
+        let key = mask.unwrap();
+        let payload: Vec<u8> = buf[header_len..header_len + len].iter().enumerate().map(|(i, x)| x ^ key[i % 4]).collect();
+        assert_eq!(payload, b"hello");
+    }
+
+    #[test]
+    fn too_large() {
+        let mut buf = vec![0x82, 127];
+        buf.extend_from_slice(&(MAX_RECEIVE as u64 + 1).to_be_bytes());
+        assert!(parse_frame_header(&buf).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use api::serde::Deserialize;
+
+mod padding;
+mod http;
+mod tls;
+
+use padding::{Obfs, Padding, Role, HEADER_LEN, MAX_FRAME_DATA, DEFAULT_MAX_PADDING};
+
+struct Component;
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            padding: Option<&'a str>,
+            max_padding: Option<usize>,
+            buckets: Option<&'a str>, // comma-separated frame sizes
+            split: Option<usize>,
+            dummy: Option<u64>, // ms
+
+            host: Option<&'a str>,
+            path: Option<&'a str>,
+            sni: Option<&'a str>,
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("obfs must have exactly 1 output")
+        }
+
+        let leak = |x: &str| &*Box::leak(Box::<str>::from(x));
+
+        match config.function_name {
+            "obfs_encode" | "obfs_decode" => {
+                let padding = match config.padding.unwrap_or("random") {
+                    "none" => Padding::None,
+                    "random" => {
+                        let max = config.max_padding.unwrap_or(DEFAULT_MAX_PADDING);
+                        if max > u16::MAX as usize {
+                            panic!("obfs max_padding must be at most 65535")
+                        }
+                        Padding::Random(max)
+                    }
+                    "bucket" => {
+                        let mut buckets: Vec<usize> = config.buckets.unwrap_or("128,256,512,1024,1400").split(',')
+                            .map(|x| x.trim().parse().expect("obfs buckets must be comma-separated numbers"))
+                            .collect();
+                        buckets.sort_unstable();
+                        if buckets[0] <= HEADER_LEN || *buckets.last().unwrap() > u16::MAX as usize {
+                            panic!("obfs buckets must be between {} and 65535", HEADER_LEN + 1)
+                        }
+                        Padding::Bucket(buckets)
+                    }
+                    x => panic!("unknown obfs padding: {}", x)
+                };
+
+                if config.split.is_some_and(|x| x == 0 || x > MAX_FRAME_DATA) {
+                    panic!("obfs split must be between 1 and 65535")
+                }
+
+                if config.dummy == Some(0) {
+                    panic!("obfs dummy interval must be positive")
+                }
+
+                Box::new(Obfs {
+                    padding,
+                    split: config.split,
+                    dummy: config.dummy.map(Duration::from_millis),
+                    role: if config.function_name == "obfs_encode" { Role::Encoder } else { Role::Decoder }
+                })
+            }
+            "http_obfs_client" => Box::new(http::Client {
+                host: leak(config.host.expect("http_obfs_client requires host")),
+                path: leak(config.path.unwrap_or("/"))
+            }),
+            "http_obfs_server" => Box::new(http::Server {
+                host: config.host.map(leak),
+                path: config.path.map(leak)
+            }),
+            "tls_obfs_client" => Box::new(tls::Client {
+                sni: leak(config.sni.expect("tls_obfs_client requires sni"))
+            }),
+            "tls_obfs_server" => Box::new(tls::Server {
+                sni: config.sni.map(leak)
+            }),
+            _ => unreachable!()
+        }
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["obfs_encode", "obfs_decode", "http_obfs_client", "http_obfs_server", "tls_obfs_client", "tls_obfs_server"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "obfs"
+    }
+}
+
+/// log a rejected handshake with the origin if known
+fn reject(metadata: &api::MetaData, name: &str, reason: &str) {
+    if let Some(origin) = metadata.get::<std::net::SocketAddr>("origin_addr") {
+        eprintln!("{}: {} from {}", name, reason, origin)
+    } else {
+        eprintln!("{}: {}", name, reason)
+    }
+}
+
+fn warn_datagram(metadata: &api::MetaData, name: &str) {
+    if metadata.get::<bool>("datagram").copied().unwrap_or(false) {
+        eprintln!("WARNING: {} is not designed for datagrams", name)
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
@@ -0,0 +1,267 @@
+use std::time::Duration;
+
+use rand::Rng;
+use ring::digest;
+
+#[derive(Clone, Copy)]
+pub enum Role { Encoder, Decoder }
+
+pub const HEADER_LEN: usize = 5; // kind (u8) + data length (u16) + padding length (u16)
+pub const SEED_LEN: usize = 8;
+pub const MAX_FRAME_DATA: usize = u16::MAX as usize;
+pub const DEFAULT_MAX_PADDING: usize = 256;
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum Kind { Data, Dummy }
+
+pub enum Padding {
+    None,
+    Random(usize), // uniformly 0 to the given length
+    Bucket(Vec<usize>) // round the frame up to the smallest bucket, or a multiple of the largest one
+}
+
+pub struct Obfs {
+    pub padding: Padding,
+    pub split: Option<usize>,
+    pub dummy: Option<Duration>,
+    pub role: Role
+}
+
+impl<R: api::Runtime> api::Actor<R> for Obfs {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let datagram = metadata.get::<bool>("datagram").copied().unwrap_or(false);
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        match self.role {
+            Role::Encoder => {
+                runtime.spawn_task(self.encode(datagram, forward_address, mailbox.expect("no mailbox")));
+                if let Some(address) = address { // datagram sources may not accept replies
+                    runtime.spawn_task(decode(datagram, address, backward_mailbox));
+                }
+            }
+            Role::Decoder => {
+                runtime.spawn_task(decode(datagram, forward_address, mailbox.expect("no mailbox")));
+                if let Some(address) = address {
+                    runtime.spawn_task(self.encode(datagram, address, backward_mailbox));
+                }
+            }
+        }
+    }
+
+    fn spawn_composite(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let datagram = metadata.get::<bool>("datagram").copied().unwrap_or(false);
+        match self.role {
+            Role::Encoder => runtime.spawn_task(self.encode(datagram, address.expect("no address"), mailbox.expect("no mailbox"))),
+            Role::Decoder => runtime.spawn_task(decode(datagram, address.expect("no address"), mailbox.expect("no mailbox")))
+        }
+    }
+}
+
+/// Headers are XORed with a keystream so they look random and do not reveal the lengths of what they carry, wherever
+/// obfs is placed. The seed is sent in clear before the first frame; the mask of the n-th frame is the first bytes of
+/// SHA-256(seed || n).
+struct Mask {
+    seed: [u8; SEED_LEN],
+    counter: u64 // the index of the next frame
+}
+
+impl Mask {
+    fn new(seed: [u8; SEED_LEN]) -> Self {
+        Self { seed, counter: 0 }
+    }
+
+    fn random() -> Self {
+        Self::new(rand::thread_rng().gen())
+    }
+
+    fn get(&self) -> [u8; HEADER_LEN] {
+        let mut context = digest::Context::new(&digest::SHA256);
+        context.update(&self.seed);
+        context.update(&self.counter.to_be_bytes());
+        context.finish().as_ref()[..HEADER_LEN].try_into().unwrap()
+    }
+
+    fn apply(&self, header: &mut [u8]) {
+        header.iter_mut().zip(self.get()).for_each(|(x, m)| *x ^= m)
+    }
+}
+
+impl Obfs {
+    /// Each message becomes one or more frames. For datagrams, a message is always a single frame so the decoder can
+    /// handle each datagram on its own. Dummy frames are sent at random intervals around `dummy` in addition to the data.
+    /// Each datagram carries its own seed, while a stream sends one at the start.
+    async fn encode(&self, datagram: bool, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut next_dummy = self.dummy.map(|x| tokio::time::Instant::now() + jitter(x));
+        let mut mask = Mask::random();
+
+        loop {
+            let msg = match next_dummy {
+                Some(deadline) => match tokio::time::timeout_at(deadline, mail.recv()).await {
+                    Ok(msg) => msg,
+                    Err(_) => {
+                        next_dummy = Some(tokio::time::Instant::now() + jitter(self.dummy.unwrap()));
+                        if datagram {
+                            mask = Mask::random()
+                        }
+                        if addr.send(self.frame(&mut mask, Kind::Dummy, &[])).await.is_err() {
+                            return
+                        }
+                        continue
+                    }
+                },
+                None => mail.recv().await
+            };
+
+            let msg = match msg {
+                Some(msg) => msg,
+                None => return
+            };
+
+            if datagram {
+                if msg.len() > MAX_FRAME_DATA {
+                    eprintln!("obfs: datagram too large, dropped");
+                    continue
+                }
+                mask = Mask::random();
+                if addr.send(self.frame(&mut mask, Kind::Data, &msg)).await.is_err() {
+                    return
+                }
+                continue
+            }
+
+            for chunk in self.split(&msg) {
+                if addr.send(self.frame(&mut mask, Kind::Data, chunk)).await.is_err() {
+                    return
+                }
+            }
+        }
+    }
+
+    /// split a message into chunks of random sizes between half of `split` and `split`
+    fn split<'a>(&self, msg: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut rng = rand::thread_rng();
+        let mut chunks = vec![];
+        let mut rest = msg;
+
+        loop {
+            let len = match self.split {
+                Some(max) => rng.gen_range(max.div_ceil(2)..=max),
+                None => MAX_FRAME_DATA
+            };
+            if rest.len() <= len {
+                chunks.push(rest);
+                return chunks
+            }
+            chunks.push(&rest[..len]);
+            rest = &rest[len..]
+        }
+    }
+
+    /// a frame with a masked header, preceded by the seed if it is the first frame of the mask
+    fn frame(&self, mask: &mut Mask, kind: Kind, data: &[u8]) -> Box<[u8]> {
+        let mut rng = rand::thread_rng();
+        let len = HEADER_LEN + data.len();
+        let padding = match (&self.padding, kind) {
+            (Padding::None, Kind::Data) => 0,
+            (Padding::None, Kind::Dummy) => rng.gen_range(0..=DEFAULT_MAX_PADDING), // dummy frames should not look empty
+            (Padding::Random(max), _) => rng.gen_range(0..=*max),
+            (Padding::Bucket(buckets), _) => match buckets.iter().find(|&&x| x >= len) {
+                Some(bucket) => bucket - len,
+                None => {
+                    let largest = *buckets.last().unwrap();
+                    (largest - len % largest) % largest
+                }
+            }
+        };
+
+        let mut buf = Vec::with_capacity(SEED_LEN + len + padding);
+        if mask.counter == 0 {
+            buf.extend_from_slice(&mask.seed)
+        }
+        let start = buf.len();
+        buf.push(kind as u8);
+        buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&(padding as u16).to_be_bytes());
+        mask.apply(&mut buf[start..]);
+        mask.counter += 1;
+        buf.extend_from_slice(data);
+        buf.resize(start + len + padding, 0);
+        rng.fill(&mut buf[start + len..]);
+        buf.into()
+    }
+}
+
+/// a random duration between 0.5x and 1.5x of the interval
+fn jitter(interval: Duration) -> Duration {
+    interval.mul_f64(rand::thread_rng().gen_range(0.5..1.5))
+}
+
+/// Strip the padding and drop dummy frames. For streams, frames can span messages. For datagrams, each message must
+/// contain its seed and whole frames, and malformed ones are dropped.
+async fn decode(datagram: bool, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+    let mut buf: Vec<u8> = vec![];
+    let mut mask: Option<Mask> = None;
+
+    while let Some(msg) = mail.recv().await {
+        buf.extend_from_slice(&msg);
+
+        if datagram {
+            mask = None
+        }
+        let mask = match &mut mask {
+            Some(mask) => mask,
+            None if buf.len() >= SEED_LEN => mask.insert(Mask::new(buf.drain(..SEED_LEN).as_slice().try_into().unwrap())),
+            None if datagram => {
+                eprintln!("obfs: malformed datagram");
+                buf.clear();
+                continue
+            }
+            None => continue
+        };
+
+        let mut offset = 0;
+        let mut malformed = false;
+        while buf.len() - offset >= HEADER_LEN {
+            let mut header: [u8; HEADER_LEN] = buf[offset..offset + HEADER_LEN].try_into().unwrap();
+            mask.apply(&mut header);
+            let data_len = u16::from_be_bytes(header[1..3].try_into().unwrap()) as usize;
+            let padding = u16::from_be_bytes(header[3..5].try_into().unwrap()) as usize;
+            if buf.len() - offset < HEADER_LEN + data_len + padding {
+                break
+            }
+
+            match header[0] {
+                x if x == Kind::Data as u8 => {
+                    let data = buf[offset + HEADER_LEN..offset + HEADER_LEN + data_len].into();
+                    if addr.send(data).await.is_err() {
+                        return
+                    }
+                }
+                x if x == Kind::Dummy as u8 => {}
+                _ => {
+                    malformed = true;
+                    break
+                }
+            }
+
+            offset += HEADER_LEN + data_len + padding;
+            mask.counter += 1;
+        }
+
+        if datagram && (malformed || offset < buf.len()) {
+            eprintln!("obfs: malformed datagram");
+            buf.clear();
+            continue
+        }
+
+        if malformed {
+            eprintln!("obfs: malformed frame");
+            return
+        }
+
+        buf.drain(..offset);
+    }
+}
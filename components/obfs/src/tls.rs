@@ -0,0 +1,295 @@
+//! Disguise the stream as TLS 1.3. The client starts with a ClientHello carrying the SNI, the server replies with a
+//! ServerHello and a ChangeCipherSpec like a real TLS 1.3 server, and then data are sent in application data records.
+
+use api::Address;
+use rand::Rng;
+
+use crate::{reject, warn_datagram};
+
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+const MAX_RECORD: usize = 16384;
+const MAX_EXPANSION: usize = 256; // TLS 1.3 allows records to be this much larger than the plaintext limit
+
+pub struct Client {
+    pub sni: &'static str
+}
+
+impl<R: api::Runtime> api::Actor<R> for Client {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        warn_datagram(&metadata, "tls_obfs_client");
+
+        let (mut forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+
+        let mailbox = mailbox.expect("no mailbox");
+        runtime.spawn_task(async move {
+            if forward_address.send(client_hello(self.sni).into()).await.is_err() {
+                return
+            }
+            seal(forward_address, mailbox).await
+        });
+        runtime.spawn_task(open("tls_obfs_client", address.expect("no address"), Reader::new(backward_mailbox)));
+    }
+}
+
+/// Checks the ClientHello and the SNI if `sni` is given. Failed clients get a handshake_failure alert.
+pub struct Server {
+    pub sni: Option<&'static str>
+}
+
+impl<R: api::Runtime> api::Actor<R> for Server {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        warn_datagram(&metadata, "tls_obfs_server");
+
+        let mut address = address.expect("no address");
+        let mut reader = Reader::new(mailbox.expect("no mailbox"));
+
+        runtime.spawn_task_with_runtime(move |runtime| async move {
+            let hello = match reader.next().await {
+                Ok((HANDSHAKE, hello)) => hello,
+                Ok(_) => return reject(&metadata, "tls_obfs_server", "unexpected record"),
+                Err(Some(reason)) => return reject(&metadata, "tls_obfs_server", reason),
+                Err(None) => return
+            };
+
+            let checked = parse_client_hello(&hello).ok_or("malformed client hello").and_then(|(session_id, sni)| {
+                if self.sni.is_some_and(|x| Some(x) != sni) {
+                    return Err("unexpected sni")
+                }
+                Ok(session_id)
+            });
+
+            let session_id = match checked {
+                Ok(x) => x,
+                Err(reason) => {
+                    reject(&metadata, "tls_obfs_server", reason);
+                    let _ = address.send(record(ALERT, 0x0303, &[2, 40]).into()).await; // fatal handshake_failure
+                    return
+                }
+            };
+
+            if address.send(server_hello(session_id).into()).await.is_err() {
+                return
+            }
+
+            let (forward_address, forward_mailbox) = runtime.channel();
+            let (backward_address, backward_mailbox) = runtime.channel();
+            runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+            runtime.spawn_task(seal(address, backward_mailbox));
+            open("tls_obfs_server", forward_address, reader).await
+        });
+    }
+}
+
+fn record(content_type: u8, version: u16, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(5 + payload.len());
+    buf.push(content_type);
+    buf.extend_from_slice(&version.to_be_bytes());
+    buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+fn handshake(handshake_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![handshake_type];
+    buf.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    buf.extend_from_slice(body);
+    buf
+}
+
+fn extension(buf: &mut Vec<u8>, extension_type: u16, data: &[u8]) {
+    buf.extend_from_slice(&extension_type.to_be_bytes());
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+/// a ClientHello that resembles the ones of common browsers
+fn client_hello(sni: &str) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+
+    let mut hello = vec![0x03, 0x03];
+    hello.extend_from_slice(&rng.gen::<[u8; 32]>()); // random
+    hello.push(32);
+    hello.extend_from_slice(&rng.gen::<[u8; 32]>()); // legacy session id
+
+    let suites: &[u16] = &[0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014, 0x009c, 0x009d, 0x002f, 0x0035];
+    hello.extend_from_slice(&(suites.len() as u16 * 2).to_be_bytes());
+    for suite in suites {
+        hello.extend_from_slice(&suite.to_be_bytes())
+    }
+    hello.extend_from_slice(&[1, 0]); // null compression
+
+    let mut server_name = ((sni.len() + 3) as u16).to_be_bytes().to_vec();
+    server_name.push(0); // host_name
+    server_name.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+    server_name.extend_from_slice(sni.as_bytes());
+
+    let mut key_share = vec![0x00, 0x24, 0x00, 0x1d, 0x00, 0x20]; // x25519
+    key_share.extend_from_slice(&rng.gen::<[u8; 32]>());
+
+    let mut extensions = vec![];
+    extension(&mut extensions, 0x0000, &server_name);
+    extension(&mut extensions, 0x0017, &[]); // extended_master_secret
+    extension(&mut extensions, 0xff01, &[0]); // renegotiation_info
+    extension(&mut extensions, 0x000a, &[0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18]); // supported_groups
+    extension(&mut extensions, 0x000b, &[1, 0]); // ec_point_formats
+    extension(&mut extensions, 0x0023, &[]); // session_ticket
+    extension(&mut extensions, 0x0010, b"\x00\x0c\x02h2\x08http/1.1"); // alpn
+    extension(&mut extensions, 0x000d, &[0x00, 0x10, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01, 0x08, 0x06, 0x06, 0x01]); // signature_algorithms
+    extension(&mut extensions, 0x0033, &key_share);
+    extension(&mut extensions, 0x002d, &[1, 1]); // psk_key_exchange_modes
+    extension(&mut extensions, 0x002b, &[4, 0x03, 0x04, 0x03, 0x03]); // supported_versions
+
+    hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    hello.extend_from_slice(&extensions);
+
+    record(HANDSHAKE, 0x0301, &handshake(1, &hello))
+}
+
+/// a TLS 1.3 ServerHello followed by the ChangeCipherSpec for middlebox compatibility
+fn server_hello(session_id: &[u8]) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+
+    let mut hello = vec![0x03, 0x03];
+    hello.extend_from_slice(&rng.gen::<[u8; 32]>());
+    hello.push(session_id.len() as u8);
+    hello.extend_from_slice(session_id);
+    hello.extend_from_slice(&[0x13, 0x01, 0]); // TLS_AES_128_GCM_SHA256, null compression
+
+    let mut key_share = vec![0x00, 0x1d, 0x00, 0x20];
+    key_share.extend_from_slice(&rng.gen::<[u8; 32]>());
+
+    let mut extensions = vec![];
+    extension(&mut extensions, 0x002b, &[0x03, 0x04]);
+    extension(&mut extensions, 0x0033, &key_share);
+
+    hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    hello.extend_from_slice(&extensions);
+
+    let mut buf = record(HANDSHAKE, 0x0303, &handshake(2, &hello));
+    buf.extend_from_slice(&record(CHANGE_CIPHER_SPEC, 0x0303, &[1]));
+    buf
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None
+        }
+        let (x, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(x)
+    }
+
+    fn u8(&mut self) -> Option<usize> {
+        self.take(1).map(|x| x[0] as usize)
+    }
+
+    fn u16(&mut self) -> Option<usize> {
+        self.take(2).map(|x| u16::from_be_bytes([x[0], x[1]]) as usize)
+    }
+}
+
+/// parse a ClientHello handshake message. Returns the legacy session id and the SNI if any.
+fn parse_client_hello(msg: &[u8]) -> Option<(&[u8], Option<&str>)> {
+    let mut cursor = Cursor(msg);
+    if cursor.u8()? != 1 {
+        return None
+    }
+    let len = cursor.take(3)?;
+    let mut hello = Cursor(cursor.take(u32::from_be_bytes([0, len[0], len[1], len[2]]) as usize)?);
+
+    hello.take(2 + 32)?; // version and random
+    let n = hello.u8()?;
+    if n > 32 {
+        return None
+    }
+    let session_id = hello.take(n)?;
+    let n = hello.u16()?;
+    hello.take(n)?; // cipher suites
+    let n = hello.u8()?;
+    hello.take(n)?; // compression methods
+
+    let mut sni = None;
+    if let Some(n) = hello.u16() {
+        let mut extensions = Cursor(hello.take(n)?);
+        while !extensions.0.is_empty() {
+            let extension_type = extensions.u16()?;
+            let n = extensions.u16()?;
+            let mut data = Cursor(extensions.take(n)?);
+            if extension_type == 0 {
+                data.u16()?;
+                if data.u8()? == 0 {
+                    let n = data.u16()?;
+                    sni = std::str::from_utf8(data.take(n)?).ok()
+                }
+            }
+        }
+    }
+
+    Some((session_id, sni))
+}
+
+/// reads TLS records from a mailbox
+struct Reader<M: api::Mailbox> {
+    mailbox: M,
+    buf: Vec<u8>
+}
+
+impl<M: api::Mailbox> Reader<M> {
+    fn new(mailbox: M) -> Self {
+        Reader { mailbox, buf: vec![] }
+    }
+
+    /// returns the content type and the payload. The error is None if the stream closed.
+    async fn next(&mut self) -> Result<(u8, Box<[u8]>), Option<&'static str>> {
+        loop {
+            if self.buf.len() >= 5 {
+                let len = u16::from_be_bytes(self.buf[3..5].try_into().unwrap()) as usize;
+                if len > MAX_RECORD + MAX_EXPANSION {
+                    return Err(Some("record too large"))
+                }
+                if self.buf.len() >= 5 + len {
+                    let record = (self.buf[0], Box::from(&self.buf[5..5 + len]));
+                    self.buf.drain(..5 + len);
+                    return Ok(record)
+                }
+            }
+
+            self.buf.extend_from_slice(&self.mailbox.recv().await.ok_or(None)?)
+        }
+    }
+}
+
+async fn seal(mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+    while let Some(msg) = mail.recv().await {
+        for chunk in msg.chunks(MAX_RECORD) {
+            if addr.send(record(APPLICATION_DATA, 0x0303, chunk).into()).await.is_err() {
+                return
+            }
+        }
+    }
+}
+
+/// deliver application data and skip the handshake records
+async fn open(name: &str, mut addr: impl api::Address, mut reader: Reader<impl api::Mailbox>) {
+    loop {
+        match reader.next().await {
+            Ok((APPLICATION_DATA, payload)) => if addr.send(payload).await.is_err() {
+                return
+            },
+            Ok((HANDSHAKE | CHANGE_CIPHER_SPEC, _)) => {}
+            Ok((ALERT, _)) => return eprintln!("{}: received alert", name),
+            Ok(_) => return eprintln!("{}: unexpected record", name),
+            Err(Some(reason)) => return eprintln!("{}: {}", name, reason),
+            Err(None) => return
+        }
+    }
+}
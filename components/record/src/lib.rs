@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use api::serde::Deserialize;
+
+mod record;
+mod replay;
+
+struct Component;
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            path: &'a str,
+
+            speed: Option<u64>, // percent
+            #[serde(default)]
+            fast: bool,
+            #[serde(default)]
+            verify: bool,
+            timeout: Option<u64>, // ms
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("{} must have exactly 1 output", config.function_name)
+        }
+
+        match config.function_name {
+            "record" => Box::new(record::Recorder::new(config.path)),
+            "replay" => {
+                if config.speed == Some(0) {
+                    panic!("replay speed must be positive")
+                }
+
+                Box::new(replay::Replayer {
+                    streams: replay::load(config.path),
+                    speed: if config.fast { None } else { Some(config.speed.unwrap_or(100)) },
+                    verify: config.verify,
+                    timeout: Duration::from_millis(config.timeout.unwrap_or(5000))
+                })
+            }
+            _ => unreachable!()
+        }
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["record", "replay"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "record"
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction { Forward, Backward }
+
+impl Direction {
+    fn symbol(self) -> &'static str {
+        match self {
+            Direction::Forward => ">",
+            Direction::Backward => "<"
+        }
+    }
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Box<[u8]>> {
+    if !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
@@ -0,0 +1,76 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock, atomic::{AtomicBool, AtomicU64, Ordering}};
+use std::time::Instant;
+
+use api::{Address, Mailbox};
+
+use super::{Direction, encode_hex};
+
+pub struct Recorder {
+    file: Mutex<File>,
+    epoch: OnceLock<Instant>, // when the first stream opened
+    count: AtomicU64, // for stream ids
+    failed: AtomicBool // stop recording after the first write error
+}
+
+impl Recorder {
+    pub fn new(path: &str) -> Self {
+        let file = File::create(path).unwrap_or_else(|e| panic!("record cannot create {}: {}", path, e));
+        Recorder { file: Mutex::new(file), epoch: OnceLock::new(), count: 0.into(), failed: false.into() }
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Recorder {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let id = self.count.fetch_add(1, Ordering::Relaxed);
+
+        let mut line = "open".to_string();
+        if let Some(addr) = metadata.get::<String>("destination_addr") {
+            write!(line, " destination_addr={}", addr).unwrap()
+        }
+        if let Some(port) = metadata.get::<u16>("destination_port") {
+            write!(line, " destination_port={}", port).unwrap()
+        }
+        if metadata.get::<bool>("datagram").copied().unwrap_or(false) {
+            line.push_str(" datagram")
+        }
+        self.write(id, &line);
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        runtime.spawn_task(self.pass(id, Direction::Forward, forward_address, mailbox.expect("no mailbox")));
+        if let Some(address) = address { // datagram sources may not accept replies
+            runtime.spawn_task(self.pass(id, Direction::Backward, address, backward_mailbox));
+        }
+    }
+}
+
+impl Recorder {
+    async fn pass(&self, id: u64, direction: Direction, mut addr: impl Address, mut mail: impl Mailbox) {
+        while let Some(msg) = mail.recv().await {
+            self.write(id, &format!("{} {}", direction.symbol(), encode_hex(&msg)));
+            if addr.send(msg).await.is_err() {
+                return
+            }
+        }
+        self.write(id, &format!("{} end", direction.symbol()))
+    }
+
+    /// write a line prefixed with the time in microseconds and the stream id
+    fn write(&self, id: u64, line: &str) {
+        if self.failed.load(Ordering::Relaxed) {
+            return
+        }
+
+        // take the time under the lock so the lines are in order
+        let mut file = self.file.lock().unwrap();
+        let time = self.epoch.get_or_init(Instant::now).elapsed().as_micros();
+        if let Err(e) = file.write_all(format!("{} {} {}\n", time, id, line).as_bytes()) {
+            eprintln!("record: write error, stop recording: {}", e);
+            self.failed.store(true, Ordering::Relaxed)
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use api::{Address, Mailbox};
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+
+use super::{Direction, decode_hex};
+
+pub struct Stream {
+    id: u64,
+    start: u64, // us
+    destination_addr: Option<String>,
+    destination_port: Option<u16>,
+    datagram: bool,
+    events: Vec<Event>,
+    expected: Vec<u8> // all recorded backward data
+}
+
+struct Event {
+    time: u64, // us
+    direction: Direction,
+    data: Option<Box<[u8]>> // None for the end of the direction
+}
+
+/// parse a recording. Panics on malformed files, as they are part of the configuration.
+pub fn load(path: &str) -> Vec<Stream> {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("replay cannot read {}: {}", path, e));
+    let mut streams: BTreeMap<u64, Stream> = BTreeMap::new();
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+
+        let malformed = || -> ! { panic!("replay: malformed line {} in {}", n + 1, path) };
+        let mut parts = line.split_whitespace();
+        let time: u64 = parts.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| malformed());
+        let id: u64 = parts.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| malformed());
+
+        let direction = match parts.next() {
+            Some("open") => {
+                let mut stream = Stream {
+                    id, start: time,
+                    destination_addr: None, destination_port: None, datagram: false,
+                    events: vec![], expected: vec![]
+                };
+                for part in parts {
+                    match part.split_once('=') {
+                        Some(("destination_addr", addr)) => stream.destination_addr = Some(addr.to_string()),
+                        Some(("destination_port", port)) => stream.destination_port = Some(port.parse().unwrap_or_else(|_| malformed())),
+                        None if part == "datagram" => stream.datagram = true,
+                        _ => malformed()
+                    }
+                }
+                if streams.insert(id, stream).is_some() {
+                    panic!("replay: stream {} is opened twice in {}", id, path)
+                }
+                continue
+            }
+            Some(">") => Direction::Forward,
+            Some("<") => Direction::Backward,
+            _ => malformed()
+        };
+
+        let data = match parts.next() {
+            Some("end") => None,
+            Some(hex) => Some(decode_hex(hex).unwrap_or_else(|| malformed())),
+            None => Some(Box::default()) // empty message
+        };
+        if parts.next().is_some() {
+            malformed()
+        }
+
+        let stream = streams.get_mut(&id).unwrap_or_else(|| panic!("replay: stream {} is not opened at line {} in {}", id, n + 1, path));
+        if let (Direction::Backward, Some(data)) = (direction, &data) {
+            stream.expected.extend_from_slice(data)
+        }
+        stream.events.push(Event { time, direction, data });
+    }
+
+    let mut streams: Vec<_> = streams.into_values().collect();
+    streams.sort_by_key(|x| x.start);
+    streams
+}
+
+pub struct Replayer {
+    pub streams: Vec<Stream>,
+    pub speed: Option<u64>, // percent, None to replay without delays
+    pub verify: bool,
+    pub timeout: Duration
+}
+
+impl<R: api::Runtime> api::Actor<R> for Replayer {
+    fn spawn_source(&'static self, runtime: R) {
+        runtime.spawn_task_with_runtime(move |runtime| self.run(runtime))
+    }
+}
+
+impl Replayer {
+    async fn run<R: api::Runtime>(&'static self, runtime: R) {
+        let start = Instant::now();
+        let (results, mut results_rx) = mpsc::unbounded_channel();
+        for stream in &self.streams {
+            let results = results.clone();
+            runtime.spawn_task_with_runtime(move |runtime| async move {
+                let _ = results.send(self.replay(runtime, stream, start).await);
+            })
+        }
+        drop(results);
+
+        let mut failed = 0;
+        while let Some(ok) = results_rx.recv().await {
+            failed += !ok as usize
+        }
+
+        if self.verify {
+            if failed > 0 {
+                eprintln!("replay: {} of {} streams mismatched", failed, self.streams.len());
+                std::process::exit(1)
+            }
+            eprintln!("replay: all {} streams matched", self.streams.len())
+        }
+    }
+
+    /// Replay the forward traffic of a stream. Returns whether the backward traffic matches the recording, or true if
+    /// not verifying.
+    async fn replay<R: api::Runtime>(&self, runtime: R, stream: &'static Stream, start: Instant) -> bool {
+        self.sleep_until(start, stream.start).await;
+
+        let mut metadata = api::MetaData::default();
+        metadata.set("stream_id".into(), stream.id);
+        if let Some(addr) = &stream.destination_addr {
+            metadata.set("destination_addr".into(), addr.clone());
+        }
+        if let Some(port) = stream.destination_port {
+            metadata.set("destination_port".into(), port);
+        }
+        if stream.datagram {
+            metadata.set("datagram".into(), true);
+        }
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+
+        // replies are collected in the background so the downstream never blocks on them
+        let received = Arc::new(Mutex::new(vec![]));
+        let (progress_tx, mut progress) = watch::channel((0, false)); // bytes received, whether ended
+        runtime.spawn_task(collect(backward_mailbox, received.clone(), progress_tx));
+
+        let mut forward = Some(forward_address);
+        let mut replied = 0; // the recorded backward bytes so far
+        for event in &stream.events {
+            if event.direction == Direction::Backward {
+                replied += event.data.as_ref().map(|x| x.len()).unwrap_or(0);
+                continue
+            }
+
+            self.sleep_until(start, event.time).await;
+
+            // keep the causality of the recording: wait for the replies that came before this message
+            let _ = tokio::time::timeout(self.timeout, progress.wait_for(|&(n, ended)| n >= replied || ended)).await;
+
+            let sent = match (&event.data, &mut forward) {
+                (Some(data), Some(addr)) => addr.send(data.clone()).await.is_ok(),
+                _ => false // the end, or the downstream stopped reading
+            };
+            if !sent {
+                forward = None
+            }
+        }
+
+        // wait for the replies to end, or to be idle for `timeout`
+        while !progress.borrow().1 {
+            if !matches!(tokio::time::timeout(self.timeout, progress.changed()).await, Ok(Ok(()))) {
+                break
+            }
+        }
+        drop(forward);
+
+        if !self.verify {
+            return true
+        }
+
+        let received = received.lock().unwrap();
+        if *received != stream.expected {
+            let offset = received.iter().zip(&stream.expected).position(|(a, b)| a != b).unwrap_or(received.len().min(stream.expected.len()));
+            eprintln!("replay: stream {} differs at byte {} (received {} bytes, expected {})", stream.id, offset, received.len(), stream.expected.len());
+            return false
+        }
+        true
+    }
+
+    async fn sleep_until(&self, start: Instant, time: u64) {
+        if let Some(speed) = self.speed {
+            tokio::time::sleep_until(start + Duration::from_micros(time * 100 / speed)).await
+        }
+    }
+}
+
+async fn collect(mut mailbox: impl Mailbox, received: Arc<Mutex<Vec<u8>>>, progress: watch::Sender<(usize, bool)>) {
+    loop {
+        tokio::select! {
+            msg = mailbox.recv() => match msg {
+                Some(msg) => {
+                    let mut received = received.lock().unwrap();
+                    received.extend_from_slice(&msg);
+                    progress.send_replace((received.len(), false));
+                }
+                None => break
+            },
+            _ = progress.closed() => return // the replay is over
+        }
+    }
+    progress.send_modify(|x| x.1 = true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// write the recording to a temporary file and load it
+    fn load_str(name: &str, content: &str) -> Vec<Stream> {
+        let path = std::env::temp_dir().join(format!("sopipe-replay-{}-{}.txt", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        let result = std::panic::catch_unwind(|| load(path.to_str().unwrap()));
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+
+    #[test]
+    fn parse() {
+        let streams = load_str("parse", "
+            # a comment
+            200 1 open destination_addr=example.com destination_port=80
+            0 0 open datagram
+            10 0 > 0102
+            20 0 < 03
+            30 0 <
+            40 0 < 04
+            50 0 > end
+            60 0 < end
+        ");
+        assert_eq!(streams.len(), 2);
+        assert_eq!((streams[0].id, streams[0].start, streams[0].datagram), (0, 0, true));
+        assert_eq!(streams[0].expected, [3, 4]);
+        assert_eq!(streams[0].events.len(), 6);
+        assert_eq!(streams[0].events[0].data.as_deref(), Some(&[1, 2][..]));
+        assert_eq!(streams[0].events[2].data.as_deref(), Some(&[][..]));
+        assert!(streams[0].events[4].data.is_none());
+        assert_eq!((streams[1].id, streams[1].start), (1, 200));
+        assert_eq!(streams[1].destination_addr.as_deref(), Some("example.com"));
+        assert_eq!(streams[1].destination_port, Some(80));
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed line 2")]
+    fn bad_hex() {
+        load_str("bad_hex", "0 0 open\n10 0 > 0g\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed line 2")]
+    fn odd_hex() {
+        load_str("odd_hex", "0 0 open\n10 0 > 012\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed line 1")]
+    fn bad_time() {
+        load_str("bad_time", "x 0 open\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed line 1")]
+    fn bad_metadata() {
+        load_str("bad_metadata", "0 0 open destination_port=http\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed line 2")]
+    fn trailing() {
+        load_str("trailing", "0 0 open\n10 0 > 01 02\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "stream 0 is opened twice")]
+    fn opened_twice() {
+        load_str("opened_twice", "0 0 open\n10 0 open\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "stream 1 is not opened")]
+    fn not_opened() {
+        load_str("not_opened", "0 0 open\n10 1 > 01\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot read")]
+    fn missing_file() {
+        load("/nonexistent/sopipe-replay.txt");
+    }
+}
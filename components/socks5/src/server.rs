@@ -1,7 +1,9 @@
-use api::{MetaData, Address, Mailbox, Runtime};
+use api::{MetaData, Address, Mailbox, Runtime, Status, StatusReporter, StatusWatcher};
 
 pub struct Actor;
 
+const STATUS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10); // then assume the downstream connected
+
 impl<R: Runtime> api::Actor<R> for Actor {
     fn spawn(&'static self, runtime: R, mut metadata: MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
         let mut address = address.expect("socks5 no address to return");
@@ -109,19 +111,35 @@ impl<R: Runtime> api::Actor<R> for Actor {
                 break (addr, port, buf.len() - slice.len())
             };
 
-            // write initial reply
-            let reply = Box::<[u8]>::from([5, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
-            if address.send(reply).await.is_err() {
-                return
-            }
-
-            // start forwarding data
+            // connect first, so the reply can tell the client whether it succeeded
+            let upstream = metadata.take::<R::StatusReporter>("status_reporter");
+            let (reporter, mut watcher) = runtime.status_channel();
             metadata.set("destination_addr".into(), addr);
             metadata.set("destination_port".into(), port);
+            metadata.set("status_reporter".into(), reporter);
 
             let (mut forward_address, forward_mailbox) = runtime.channel();
             let (backward_address, mut backward_mailbox) = runtime.channel();
             runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+
+            // outputs that do not report their status in time are assumed to be connected
+            let status = tokio::time::timeout(STATUS_TIMEOUT, watcher.wait()).await.unwrap_or(None);
+            let code = match status {
+                Some(Status::Connected) | None => 0,
+                Some(Status::Refused) => 5,
+                Some(Status::Unreachable) | Some(Status::TimedOut) => 4,
+                Some(Status::Failed) => 1
+            };
+
+            let reply = Box::<[u8]>::from([5, code, 0, 1, 0, 0, 0, 0, 0, 0]);
+            if address.send(reply).await.is_err() || code != 0 {
+                return // failures are told to the client by the reply, not the upstream, which might close before it is sent
+            }
+            if let (Some(status), Some(upstream)) = (status, upstream) {
+                upstream.report(status)
+            }
+
+            // start forwarding data
             runtime.spawn_task(async move {
                 #[allow(clippy::collapsible_if)]
                 if buf.len() > consumed {
@@ -97,9 +97,9 @@ impl<R: api::Runtime> api::Actor<R> for Actor {
         }
 
         if let Some(port) = port {
-            runtime.spawn_task_with_runtime(move |runtime| self.connect(runtime, (addr.unwrap(), port) , address.unwrap(), mailbox.unwrap()))
+            runtime.spawn_task_with_runtime(move |runtime| self.connect(runtime, metadata, (addr.unwrap(), port) , address.unwrap(), mailbox.unwrap()))
         } else {
-            runtime.spawn_task_with_runtime(move |runtime| self.connect(runtime, addr.unwrap(), address.unwrap(), mailbox.unwrap()))
+            runtime.spawn_task_with_runtime(move |runtime| self.connect(runtime, metadata, addr.unwrap(), address.unwrap(), mailbox.unwrap()))
         }
     }
 
@@ -110,16 +110,17 @@ impl<R: api::Runtime> api::Actor<R> for Actor {
 }
 
 impl Actor {
-    async fn connect(&self, runtime: impl api::Runtime, dest: impl ToSocketAddrs, address: impl api::Address, mailbox: impl api::Mailbox) {
+    async fn connect<R: api::Runtime>(&self, runtime: R, metadata: api::MetaData, dest: impl ToSocketAddrs, address: impl api::Address, mailbox: impl api::Mailbox) {
         match tokio::net::TcpStream::connect(dest).await {
             Ok(stream) => {
+                api::report_status::<R>(&metadata, api::Status::Connected);
                 let (reader, writer) = stream.into_split();
                 runtime.spawn_task(read_tcp(reader, address));
                 runtime.spawn_task(write_tcp(writer, mailbox));
             },
             Err(e) => {
                 eprintln!("connection error = {}", e);
-                // what to do? retry?
+                api::report_status::<R>(&metadata, api::Status::from_io_error(&e));
             },
         }
     }
@@ -142,17 +143,24 @@ impl Actor {
             match tokio::time::timeout(Duration::from_secs(1), listener.accept()).await {
                 Ok(Ok((stream, origin))) => {
                     eprintln!("Accepted connection from {:?}", origin);
+                    let (reporter, watcher) = runtime.status_channel();
                     let mut meta = api::MetaData::default();
                     meta.set("stream_type".into(), "TCP".to_string());
                     meta.set("origin_addr".into(), origin);
                     meta.set("stream_id".into(), count.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+                    meta.set("status_reporter".into(), reporter);
 
                     let (reader, writer) = stream.into_split();
                     let (forward_address, forward_mailbox) = runtime.channel();
                     let (backward_address, backward_mailbox) = runtime.channel();
                     runtime.spawn_next(0, meta, backward_address, forward_mailbox);
-                    runtime.spawn_task(read_tcp(reader, forward_address));
-                    runtime.spawn_task(write_tcp(writer, backward_mailbox));
+                    runtime.spawn_task(async move {
+                        // close the client socket as soon as the downstream fails to connect
+                        tokio::select! {
+                            _ = async { tokio::join!(read_tcp(reader, forward_address), write_tcp(writer, backward_mailbox)) } => {}
+                            _ = failed(watcher) => {}
+                        }
+                    });
 
                     if self.once {
                         return
@@ -167,6 +175,14 @@ impl Actor {
     }
 }
 
+/// resolves if the downstream reports a failure, otherwise never
+async fn failed(mut watcher: impl api::StatusWatcher) {
+    match watcher.wait().await {
+        Some(status) if status != api::Status::Connected => {}
+        _ => std::future::pending().await
+    }
+}
+
 async fn read_tcp(mut stream: impl AsyncReadExt + Unpin, mut addr: impl api::Address) {
     let mut buffer = vec![0; 65536].into_boxed_slice();
     loop {
@@ -186,9 +202,12 @@ async fn read_tcp(mut stream: impl AsyncReadExt + Unpin, mut addr: impl api::Add
 async fn write_tcp(mut stream: impl AsyncWriteExt + Unpin, mut mail: impl api::Mailbox) {
     while let Some(msg) = mail.recv().await {
         if stream.write_all(&msg).await.is_err() {
-            break
+            return
         }
     }
+
+    // half-close: the peer can still send to us
+    let _ = stream.shutdown().await;
 }
 
 pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
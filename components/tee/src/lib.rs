@@ -1,16 +1,70 @@
+use std::sync::Arc;
+
 use api::{Address, Mailbox};
+use api::serde::Deserialize;
+use tokio::sync::{mpsc, Semaphore, TryAcquireError};
 
 struct Component;
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OnError { Stop, Continue }
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Lagging { Block, Drop, Close }
+
 struct Actor {
-    n_outputs: usize
+    names: Vec<String>,
+    on_error: OnError,
+    lagging: Lagging,
+    buffer: usize,
+    backward: Option<usize> // None to merge all outputs
 }
 
 impl<R: api::Runtime> api::Component<R> for Component {
     fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
-        let n_outputs = arguments.iter().find(|(name, _)| name == "outputs").unwrap().1.as_vec().unwrap().len();
-        assert!(n_outputs >= 1);
-        Box::new(Actor { n_outputs })
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            on_error: Option<&'a str>,
+            lagging: Option<&'a str>,
+            buffer: Option<usize>,
+            backward: Option<&'a str>,
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+        assert!(!config.outputs.is_empty());
+
+        let on_error = match config.on_error.unwrap_or("stop") {
+            "stop" => OnError::Stop,
+            "continue" => OnError::Continue,
+            x => panic!("unknown tee on_error: {}", x)
+        };
+
+        let lagging = match config.lagging.unwrap_or("block") {
+            "block" => Lagging::Block,
+            "drop" => Lagging::Drop,
+            "close" => Lagging::Close,
+            x => panic!("unknown tee lagging: {}", x)
+        };
+
+        let buffer = config.buffer.unwrap_or(65536);
+        if buffer == 0 {
+            panic!("tee buffer must be positive")
+        }
+
+        let backward = match config.backward.unwrap_or("all") {
+            "all" => None,
+            name => Some(config.outputs.iter().position(|x| *x == name).unwrap_or_else(|| panic!("tee has no output named {}", name)))
+        };
+
+        Box::new(Actor {
+            names: config.outputs.iter().enumerate().map(|(i, x)| if x.is_empty() { format!("#{}", i) } else { x.to_string() }).collect(),
+            on_error, lagging, buffer, backward
+        })
     }
 
     fn functions(&self) -> &'static [&'static str] {
@@ -22,39 +76,49 @@ impl<R: api::Runtime> api::Component<R> for Component {
     }
 }
 
+/// The queue to an output. `credit` is the number of bytes that can still be queued, and is closed when the output
+/// fails.
+struct Lane {
+    index: usize,
+    queue: mpsc::UnboundedSender<Box<[u8]>>,
+    credit: Arc<Semaphore>,
+    warned: bool // whether dropping has been logged
+}
+
 impl<R: api::Runtime> api::Actor<R> for Actor {
     fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
-        let mut addresses_next = Vec::with_capacity(self.n_outputs);
-        let mut mailboxes_next = Vec::with_capacity(self.n_outputs);
+        let mut lanes = Vec::with_capacity(self.names.len());
+        let mut mailboxes_next = Vec::with_capacity(self.names.len());
+
+        for i in 0..self.names.len() {
+            // only the output that answers the origin reports the status of the stream
+            let mut metadata = metadata.clone();
+            if self.backward.is_some() && self.backward != Some(i) {
+                metadata.take::<R::StatusReporter>("status_reporter");
+            }
 
-        for i in 0..self.n_outputs {
             let (forward_address, forward_mailbox) = runtime.channel();
             let (backward_address, backward_mailbox) = runtime.channel();
-            runtime.spawn_next(i, metadata.clone(), backward_address, forward_mailbox);
-            addresses_next.push(forward_address);
+            runtime.spawn_next(i, metadata, backward_address, forward_mailbox);
+
+            let (queue, delivery) = mpsc::unbounded_channel();
+            let credit = Arc::new(Semaphore::new(self.buffer));
+            runtime.spawn_task(self.deliver(delivery, credit.clone(), forward_address));
+            lanes.push(Lane { index: i, queue, credit, warned: false });
             mailboxes_next.push(backward_mailbox);
         }
 
-        let mut mailbox = mailbox.unwrap();
-        runtime.spawn_task(async move {
-            while let Some(msg) = mailbox.recv().await {
-                let futures: Vec<_> = addresses_next.iter_mut().map(|addr| addr.send(msg.clone())).collect();
-                for future in futures {
-                    if future.await.is_err() {
-                        // TODO: what to do? should we continue for the rest?
-                        return
-                    }
-                }
-            }
-        });
+        runtime.spawn_task(self.broadcast(lanes, mailbox.expect("no mailbox")));
 
-        let address = address.unwrap();
-        for mut mailbox in mailboxes_next.into_iter() {
-            let mut address = address.clone();
+        for (i, mut mailbox) in mailboxes_next.into_iter().enumerate() {
+            let mut address = address.clone().filter(|_| self.backward.is_none() || self.backward == Some(i));
             runtime.spawn_task(async move {
                 while let Some(msg) = mailbox.recv().await {
-                    if address.send(msg).await.is_err() {
-                        return
+                    // the replies of other outputs are discarded, but still read so they are not blocked
+                    if let Some(addr) = &mut address {
+                        if addr.send(msg).await.is_err() {
+                            return
+                        }
                     }
                 }
             })
@@ -62,6 +126,71 @@ impl<R: api::Runtime> api::Actor<R> for Actor {
     }
 }
 
+impl Actor {
+    async fn broadcast(&self, mut lanes: Vec<Lane>, mut mailbox: impl Mailbox) {
+        while let Some(msg) = mailbox.recv().await {
+            let cost = self.cost(&msg);
+            let mut failed = vec![];
+
+            for (i, lane) in lanes.iter_mut().enumerate() {
+                // the output that answers the origin is the real connection and never loses data
+                let lagging = if self.backward == Some(lane.index) { Lagging::Block } else { self.lagging };
+
+                let queued = match lagging {
+                    Lagging::Block => lane.credit.acquire_many(cost).await.map(|x| x.forget()).map_err(|_| TryAcquireError::Closed),
+                    Lagging::Drop | Lagging::Close => lane.credit.try_acquire_many(cost).map(|x| x.forget())
+                };
+
+                match queued {
+                    Ok(()) => {
+                        let _ = lane.queue.send(msg.clone());
+                    }
+                    Err(TryAcquireError::NoPermits) if lagging == Lagging::Drop => {
+                        if !lane.warned {
+                            eprintln!("tee: output {} is lagging, dropping messages", self.names[lane.index]);
+                            lane.warned = true
+                        }
+                    }
+                    Err(TryAcquireError::NoPermits) => {
+                        eprintln!("tee: output {} is lagging, closing it", self.names[lane.index]);
+                        failed.push(i)
+                    }
+                    Err(TryAcquireError::Closed) => {
+                        if self.on_error == OnError::Stop {
+                            return
+                        }
+                        failed.push(i)
+                    }
+                }
+            }
+
+            // dropping the queue ends the forward direction of the output after the queued messages
+            for i in failed.into_iter().rev() {
+                lanes.remove(i);
+            }
+
+            if lanes.is_empty() {
+                return
+            }
+        }
+    }
+
+    async fn deliver(&self, mut delivery: mpsc::UnboundedReceiver<Box<[u8]>>, credit: Arc<Semaphore>, mut address: impl Address) {
+        while let Some(msg) = delivery.recv().await {
+            let cost = self.cost(&msg);
+            if address.send(msg).await.is_err() {
+                return credit.close()
+            }
+            credit.add_permits(cost as _)
+        }
+    }
+
+    /// messages larger than the buffer take the whole buffer
+    fn cost(&self, msg: &[u8]) -> u32 {
+        msg.len().min(self.buffer) as _
+    }
+}
+
 pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
     &Component {}
 }
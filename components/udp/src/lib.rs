@@ -1,7 +1,12 @@
-use api::{serde::Deserialize, Address};
+use api::serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
 
 struct Component;
 
@@ -13,6 +18,9 @@ struct Config {
     addr: api::Argument,
     port: Option<u16>,
 
+    timeout: Option<u64>, // ms
+    max_sessions: Option<usize>,
+
     outputs: Vec<String>,
     function_name: String,
 }
@@ -21,6 +29,8 @@ pub struct Actor {
     addr: Option<String>,
     port: Option<u16>,
     has_output: bool,
+    timeout: Option<Duration>,
+    max_sessions: usize,
 }
 
 impl Config {
@@ -65,8 +75,10 @@ impl Actor {
             has_output: match config.outputs.len() {
                 0 => false,
                 1 => true,
-                _ => panic!("tcp can only accept one output"),
+                _ => panic!("udp can only accept one output"),
             },
+            timeout: config.timeout.map(Duration::from_millis),
+            max_sessions: config.max_sessions.unwrap_or(1024),
         }
     }
 }
@@ -95,11 +107,11 @@ impl<R: api::Runtime> api::Actor<R> for Actor {
 
         if let Some(port) = port {
             runtime.spawn_task_with_runtime(move |runtime| {
-                self.connect(runtime, (addr.unwrap(), port), address, mailbox)
+                self.connect(runtime, metadata, (addr.unwrap(), port), address, mailbox)
             })
         } else {
             runtime.spawn_task_with_runtime(move |runtime| {
-                self.connect(runtime, addr.unwrap(), address, mailbox)
+                self.connect(runtime, metadata, addr.unwrap(), address, mailbox)
             })
         }
     }
@@ -111,18 +123,32 @@ impl<R: api::Runtime> api::Actor<R> for Actor {
 }
 
 impl Actor {
-    async fn connect(
+    async fn connect<R: api::Runtime>(
         &self,
-        runtime: impl api::Runtime,
+        runtime: R,
+        metadata: api::MetaData,
         dest: impl ToSocketAddrs,
-        address: Option<impl api::Address>,
-        mailbox: Option<impl api::Mailbox>,
+        address: Option<R::Address>,
+        mailbox: Option<R::Mailbox>,
     ) {
-        let socket = Arc::new(UdpSocket::bind(("::", 0)).await.unwrap());
-        socket.connect(dest).await.unwrap();
+        let socket = match UdpSocket::bind(("::", 0)).await {
+            Ok(socket) => Arc::new(socket),
+            Err(e) => {
+                eprintln!("udp bind error = {}", e);
+                api::report_status::<R>(&metadata, api::Status::from_io_error(&e));
+                return
+            }
+        };
+        if let Err(e) = socket.connect(dest).await {
+            eprintln!("udp connect error = {}", e);
+            api::report_status::<R>(&metadata, api::Status::from_io_error(&e));
+            return
+        }
+        // UDP has no handshake, so this only means the destination was resolved and routable
+        api::report_status::<R>(&metadata, api::Status::Connected);
 
         if let Some(address) = address {
-            runtime.spawn_task(read_udp(socket.clone(), address));
+            runtime.spawn_task(read_udp(socket.clone(), address, self.timeout.unwrap_or(CONNECT_TIMEOUT)));
         }
         if let Some(mailbox) = mailbox {
             runtime.spawn_task(write_udp(socket, mailbox));
@@ -131,44 +157,130 @@ impl Actor {
 
     async fn listen(&self, runtime: impl api::Runtime) {
         let addr = self.addr.as_deref().unwrap_or("::");
-        let listener = if let Some(port) = self.port {
+        let socket = Arc::new(if let Some(port) = self.port {
             UdpSocket::bind((addr, port)).await.unwrap()
         } else {
             UdpSocket::bind(addr).await.unwrap()
-        };
+        });
 
         while let api::RunLevel::Init = runtime.get_runlevel() {
             tokio::time::sleep(Duration::from_millis(20)).await
         }
 
-        let (mut address, mailbox) = runtime.channel();
-        let mut meta = api::MetaData::default();
-        meta.set("stream_type".into(), "UDP".to_string());
-        runtime.spawn_next(0, meta, None, mailbox);
+        // each peer gets its own stream. Replies are sent back to the peer through the same socket.
+        let start = Instant::now();
+        let mut sessions: HashMap<SocketAddr, Session> = HashMap::new();
+        let mut last_sweep = start;
+        let mut count: u64 = 0;
 
+        let mut buffer = vec![0; 65536].into_boxed_slice();
         while let api::RunLevel::Run = runtime.get_runlevel() {
-            let mut buffer = vec![0; 65536].into_boxed_slice();
-            match tokio::time::timeout(Duration::from_secs(1), listener.recv_from(&mut buffer[..])).await {
+            match tokio::time::timeout(Duration::from_secs(1), socket.recv_from(&mut buffer[..])).await {
                 Ok(Ok((n, origin))) => {
-                    eprintln!("Recieved UDP packet from {:?}", origin);
+                    if !sessions.contains_key(&origin) {
+                        if sessions.len() >= self.max_sessions {
+                            eprintln!("Too many UDP sessions. Dropped packet from {:?}", origin);
+                            continue
+                        }
 
-                    if address.send(Box::from(&buffer[..n])).await.is_err() {
-                        return;
+                        eprintln!("New UDP session from {:?}", origin);
+                        let mut meta = api::MetaData::default();
+                        meta.set("stream_type".into(), "UDP".to_string());
+                        meta.set("datagram".into(), true);
+                        meta.set("origin_addr".into(), origin);
+                        meta.set("stream_id".into(), count);
+                        count += 1;
+
+                        let (forward_address, forward_mailbox) = runtime.channel();
+                        let (backward_address, backward_mailbox) = runtime.channel();
+                        let (queue, queue_rx) = mpsc::channel(SESSION_QUEUE);
+                        let (stop, stop_rx) = oneshot::channel();
+                        let last_active = Arc::new(AtomicU64::new(0));
+                        runtime.spawn_next(0, meta, backward_address, forward_mailbox);
+                        runtime.spawn_task(forward_udp(queue_rx, forward_address));
+                        runtime.spawn_task(reply_udp(socket.clone(), origin, backward_mailbox, stop_rx, start, last_active.clone()));
+                        sessions.insert(origin, Session { queue, _stop: stop, last_active, warned: false });
+                    }
+
+                    // never wait for a session here, so a slow one does not stall the other peers
+                    let session = sessions.get_mut(&origin).unwrap();
+                    session.touch(start);
+                    match session.queue.try_send(Box::from(&buffer[..n])) {
+                        Ok(()) => {}
+                        Err(mpsc::error::TrySendError::Full(_)) => if !session.warned {
+                            eprintln!("UDP session from {:?} is lagging, dropping packets", origin);
+                            session.warned = true
+                        }
+                        Err(mpsc::error::TrySendError::Closed(_)) => {
+                            sessions.remove(&origin); // the stream is closed by the downstream
+                        }
                     }
                 }
                 Ok(Err(err)) => {
-                    eprintln!("accept error = {}", err)
+                    eprintln!("recv error = {}", err)
                 }
                 Err(_) => {} // timeout, check runlevel and listen again
             }
+
+            // dropping a session ends the stream and stops its replies. `last_active` may be updated by a reply after `now`
+            // was taken.
+            if last_sweep.elapsed() >= Duration::from_secs(1) {
+                let now = start.elapsed().as_millis() as u64;
+                let timeout = self.timeout.unwrap_or(LISTEN_TIMEOUT).as_millis() as u64;
+                sessions.retain(|_, session| now.saturating_sub(session.last_active.load(Ordering::Relaxed)) < timeout);
+                last_sweep = Instant::now();
+            }
+        }
+    }
+}
+
+const SESSION_QUEUE: usize = 64; // datagrams queued for each session before dropping
+const LISTEN_TIMEOUT: Duration = Duration::from_secs(60); // the default idle timeout of the sessions of a listener
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5); // the default idle timeout when connecting
+
+struct Session {
+    queue: mpsc::Sender<Box<[u8]>>,
+    _stop: oneshot::Sender<()>, // dropped with the session to stop its replies
+    last_active: Arc<AtomicU64>, // ms since the listener started. Updated in both directions.
+    warned: bool, // whether dropping has been logged
+}
+
+impl Session {
+    fn touch(&self, start: Instant) {
+        self.last_active.store(start.elapsed().as_millis() as _, Ordering::Relaxed)
+    }
+}
+
+async fn forward_udp(mut queue: mpsc::Receiver<Box<[u8]>>, mut addr: impl api::Address) {
+    while let Some(msg) = queue.recv().await {
+        if addr.send(msg).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn reply_udp(socket: Arc<UdpSocket>, origin: SocketAddr, mut mail: impl api::Mailbox, mut stop: oneshot::Receiver<()>, start: Instant, last_active: Arc<AtomicU64>) {
+    loop {
+        let msg = tokio::select! {
+            msg = mail.recv() => match msg {
+                Some(msg) => msg,
+                None => return
+            },
+            _ = &mut stop => return // the session is closed by the listener
+        };
+
+        if let Err(e) = socket.send_to(&msg, origin).await {
+            eprintln!("IO error: {}", e);
+            return;
         }
+        last_active.store(start.elapsed().as_millis() as _, Ordering::Relaxed)
     }
 }
 
-async fn read_udp(socket: Arc<UdpSocket>, mut addr: impl api::Address) {
+async fn read_udp(socket: Arc<UdpSocket>, mut addr: impl api::Address, timeout: Duration) {
     let mut buffer = vec![0; 65536].into_boxed_slice();
     loop {
-        match tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buffer[..])).await {
+        match tokio::time::timeout(timeout, socket.recv(&mut buffer[..])).await {
             Ok(Ok(n)) => {
                 if addr.send(buffer[..n].iter().copied().collect()).await.is_err() {
                     return;
@@ -1,9 +1,20 @@
 use api::serde::Deserialize;
+use chacha20::{XChaCha20, cipher::{KeyIvInit, StreamCipher}};
+use sha2::{Sha256, Digest};
 
 struct Component;
 
+#[derive(Clone, Copy)]
+enum Role { Both, Encoder, Decoder }
+
+const PATTERN_SIZE: usize = 4096; // the minimal number of bytes processed per pass in the fixed mode
+const NONCE_LEN: usize = 24;
+
 struct Actor {
-    key: &'static [u8]
+    pattern: &'static [u8], // the key repeated to at least PATTERN_SIZE bytes
+    key_len: usize,
+    keystream: Option<[u8; 32]>, // the chacha20 key, None in the fixed mode
+    role: Role
 }
 
 impl<R: api::Runtime> api::Component<R> for Component {
@@ -13,6 +24,7 @@ impl<R: api::Runtime> api::Component<R> for Component {
         #[serde(crate="api::serde")]
         struct Config<'a> {
             key: &'a str,
+            mode: Option<&'a str>,
             outputs: Vec<&'a str>,
             function_name: &'a str,
         }
@@ -23,13 +35,33 @@ impl<R: api::Runtime> api::Component<R> for Component {
             panic!("xor must have exactly 1 output")
         }
 
-        let key = &*Box::leak(Box::<[u8]>::from(config.key.as_bytes()));
+        if config.key.is_empty() {
+            panic!("xor key must not be empty")
+        }
+
+        let role = match config.function_name {
+            "xor" => Role::Both,
+            "xor_encode" => Role::Encoder,
+            "xor_decode" => Role::Decoder,
+            _ => unreachable!()
+        };
+
+        let keystream = match config.mode.unwrap_or("fixed") {
+            "fixed" => None,
+            "chacha20" if matches!(role, Role::Both) => panic!("xor chacha20 mode requires xor_encode and xor_decode"),
+            "chacha20" => Some(Sha256::digest(config.key.as_bytes()).into()),
+            x => panic!("unknown xor mode: {}", x)
+        };
+
+        let key = config.key.as_bytes();
+        let pattern = key.repeat(PATTERN_SIZE / key.len() + 2);
+        let pattern = &*Box::leak(pattern.into_boxed_slice());
 
-        Box::new(Actor { key })
+        Box::new(Actor { pattern, key_len: key.len(), keystream, role })
     }
 
     fn functions(&self) -> &'static [&'static str] {
-        &["xor"]
+        &["xor", "xor_encode", "xor_decode"]
     }
 
     fn name(&'static self) -> &'static str {
@@ -39,28 +71,143 @@ impl<R: api::Runtime> api::Component<R> for Component {
 
 impl<R: api::Runtime> api::Actor<R> for Actor {
     fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let datagram = metadata.get::<bool>("datagram").copied().unwrap_or(false);
         let (forward_address, forward_mailbox) = runtime.channel();
         let (backward_address, backward_mailbox) = runtime.channel();
         runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
-        runtime.spawn_task(xor(self.key, forward_address, mailbox.expect("no mailbox")));
-        runtime.spawn_task(xor(self.key, address.expect("no address"), backward_mailbox));
+        match (self.keystream, self.role) {
+            (None, _) | (_, Role::Both) => {
+                runtime.spawn_task(self.xor(datagram, forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(self.xor(datagram, address.expect("no address"), backward_mailbox));
+            }
+            (Some(key), Role::Encoder) => {
+                runtime.spawn_task(encrypt(key, datagram, forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(decrypt(key, datagram, address.expect("no address"), backward_mailbox));
+            }
+            (Some(key), Role::Decoder) => {
+                runtime.spawn_task(decrypt(key, datagram, forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(encrypt(key, datagram, address.expect("no address"), backward_mailbox));
+            }
+        }
     }
 
-    fn spawn_composite(&'static self, runtime: R, _metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
-        runtime.spawn_task(xor(self.key, address.expect("no address"), mailbox.expect("no mailbox")));
+    fn spawn_composite(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        let datagram = metadata.get::<bool>("datagram").copied().unwrap_or(false);
+        let (address, mailbox) = (address.expect("no address"), mailbox.expect("no mailbox"));
+        match (self.keystream, self.role) {
+            (None, _) | (_, Role::Both) => runtime.spawn_task(self.xor(datagram, address, mailbox)),
+            (Some(key), Role::Encoder) => runtime.spawn_task(encrypt(key, datagram, address, mailbox)),
+            (Some(key), Role::Decoder) => runtime.spawn_task(decrypt(key, datagram, address, mailbox))
+        }
     }
 }
 
-async fn xor(key: &[u8], mut addr: impl api::Address, mut mail: impl api::Mailbox) {
-    let mut count = 0;
+impl Actor {
+    /// For datagrams, each message starts from the beginning of the key so lost or reordered ones do not matter.
+    async fn xor(&self, datagram: bool, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut offset = 0;
+
+        while let Some(mut msg) = mail.recv().await {
+            if datagram {
+                offset = 0
+            }
+
+            // `pattern` holds whole periods of the key, so a slice of it starting within the first period can cover a
+            // chunk directly. Xoring two slices lets the compiler vectorize the loop.
+            for chunk in msg.chunks_mut(self.pattern.len() - self.key_len) {
+                for (c, k) in chunk.iter_mut().zip(&self.pattern[offset..]) {
+                    *c ^= k
+                }
+                offset = (offset + chunk.len()) % self.key_len
+            }
+
+            if addr.send(msg).await.is_err() {
+                break
+            }
+        }
+    }
+}
+
+/// xor with the XChaCha20 keystream. A random nonce is generated for each stream and sent before the first message.
+/// For datagrams, each message gets a nonce of its own in front of it.
+async fn encrypt(key: [u8; 32], datagram: bool, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+    let mut nonce: [u8; NONCE_LEN] = rand::random();
+    let mut cipher = XChaCha20::new(&key.into(), &nonce.into());
+    let mut first = true;
 
     while let Some(mut msg) = mail.recv().await {
-        for c in &mut msg[..] {
-            *c ^= key[count];
-            count = (count + 1) % key.len()
+        if datagram {
+            nonce = rand::random();
+            cipher = XChaCha20::new(&key.into(), &nonce.into());
+            first = true
+        }
+
+        if cipher.try_apply_keystream(&mut msg).is_err() {
+            return eprintln!("xor: keystream exhausted")
+        }
+
+        if first {
+            msg = [&nonce[..], &msg].concat().into();
+            first = false
         }
+
         if addr.send(msg).await.is_err() {
-            break
+            return
+        }
+    }
+}
+
+async fn decrypt(key: [u8; 32], datagram: bool, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+    if datagram {
+        return decrypt_datagram(key, addr, mail).await
+    }
+
+    let mut header = vec![];
+    while header.len() < NONCE_LEN {
+        match mail.recv().await {
+            Some(msg) => header.extend_from_slice(&msg),
+            None => return
+        }
+    }
+
+    let mut cipher = XChaCha20::new_from_slices(&key, &header[..NONCE_LEN]).unwrap();
+    let mut msg: Box<[u8]> = header.split_off(NONCE_LEN).into();
+
+    loop {
+        if !msg.is_empty() {
+            if cipher.try_apply_keystream(&mut msg).is_err() {
+                return eprintln!("xor: keystream exhausted")
+            }
+
+            if addr.send(msg).await.is_err() {
+                return
+            }
+        }
+
+        msg = match mail.recv().await {
+            Some(msg) => msg,
+            None => return
+        }
+    }
+}
+
+async fn decrypt_datagram(key: [u8; 32], mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+    while let Some(msg) = mail.recv().await {
+        if msg.len() < NONCE_LEN {
+            eprintln!("xor: datagram too short, dropped");
+            continue
+        }
+
+        let (nonce, content) = msg.split_at(NONCE_LEN);
+        let mut content: Box<[u8]> = content.into();
+        let mut cipher = XChaCha20::new_from_slices(&key, nonce).unwrap();
+        if cipher.try_apply_keystream(&mut content).is_err() {
+            eprintln!("xor: datagram too large, dropped");
+            continue
+        }
+
+        if addr.send(content).await.is_err() {
+            return
         }
     }
 }
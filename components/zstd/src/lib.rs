@@ -0,0 +1,201 @@
+use api::serde::Deserialize;
+use zstd_lib::stream::raw::{Encoder, Decoder, Operation, InBuffer, OutBuffer};
+
+struct Component;
+
+#[derive(Clone, Copy)]
+enum Role { Encoder, Decoder }
+
+struct Actor {
+    level: i32,
+    dictionary: Option<Vec<u8>>,
+    role: Role
+}
+
+impl<R: api::Runtime> api::Component<R> for Component {
+    fn create(&'static self, arguments: Vec<(String, api::Argument)>) -> Box<dyn api::Actor<R>> {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        #[serde(crate="api::serde")]
+        struct Config<'a> {
+            #[serde(default)]
+            level: api::Argument, // a string for negative levels, which the script has no literal for
+            dictionary: Option<&'a str>, // path
+
+            outputs: Vec<&'a str>,
+            function_name: &'a str,
+        }
+
+        let config: Config = api::parse_args(&arguments).unwrap();
+
+        if config.outputs.len() != 1 {
+            panic!("zstd must have exactly 1 output")
+        }
+
+        let level: i32 = match &config.level {
+            api::Argument::None => 3,
+            api::Argument::Int(x) => (*x).try_into().unwrap_or(i32::MAX),
+            api::Argument::String(x) => x.parse().unwrap_or_else(|_| panic!("invalid zstd level {}", x)),
+            api::Argument::Vec(_) => panic!("wrong argument type")
+        };
+        let range = zstd_lib::compression_level_range();
+        if !range.contains(&level) {
+            panic!("zstd level must be between {} and {}", range.start(), range.end())
+        }
+
+        Box::new(Actor {
+            level,
+            dictionary: config.dictionary.map(|path| std::fs::read(path).unwrap_or_else(|e| panic!("zstd: cannot read {}: {}", path, e))),
+            role: match config.function_name {
+                "zstd_deflate" => Role::Encoder,
+                "zstd_inflate" => Role::Decoder,
+                _ => unreachable!()
+            }
+        })
+    }
+
+    fn functions(&self) -> &'static [&'static str] {
+        &["zstd_deflate", "zstd_inflate"]
+    }
+
+    fn name(&'static self) -> &'static str {
+        "zstd"
+    }
+}
+
+impl<R: api::Runtime> api::Actor<R> for Actor {
+    fn spawn(&'static self, runtime: R, metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        if metadata.get::<bool>("datagram").copied().unwrap_or(false) {
+            eprintln!("WARNING: the zstd module is not designed for datagrams")
+        }
+
+        let (forward_address, forward_mailbox) = runtime.channel();
+        let (backward_address, backward_mailbox) = runtime.channel();
+        runtime.spawn_next(0, metadata, backward_address, forward_mailbox);
+        match self.role {
+            Role::Encoder => {
+                runtime.spawn_task(self.deflate(forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(self.inflate(address.expect("no address"), backward_mailbox));
+            }
+            Role::Decoder => {
+                runtime.spawn_task(self.inflate(forward_address, mailbox.expect("no mailbox")));
+                runtime.spawn_task(self.deflate(address.expect("no address"), backward_mailbox));
+            }
+        }
+    }
+
+    fn spawn_composite(&'static self, runtime: R, _metadata: api::MetaData, address: Option<R::Address>, mailbox: Option<R::Mailbox>) {
+        match self.role {
+            Role::Encoder => runtime.spawn_task(self.deflate(address.expect("no address"), mailbox.expect("no mailbox"))),
+            Role::Decoder => runtime.spawn_task(self.inflate(address.expect("no address"), mailbox.expect("no mailbox")))
+        }
+    }
+}
+
+impl Actor {
+    /// each message is flushed so that the peer can decompress it without waiting for more
+    async fn deflate(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut encoder = match &self.dictionary {
+            Some(dictionary) => Encoder::with_dictionary(self.level, dictionary),
+            None => Encoder::new(self.level)
+        }.unwrap();
+
+        let mut buffer = vec![0; 65536];
+        let mut started = false;
+        while let Some(msg) = mail.recv().await {
+            started = true;
+            let mut result = vec![];
+
+            let mut input = InBuffer::around(&msg);
+            while input.pos() < msg.len() {
+                let mut output = OutBuffer::around(&mut buffer[..]);
+                if let Err(e) = encoder.run(&mut input, &mut output) {
+                    return eprintln!("zstd: {}", e)
+                }
+                result.extend_from_slice(output.as_slice());
+            }
+
+            loop {
+                let mut output = OutBuffer::around(&mut buffer[..]);
+                let remaining = match encoder.flush(&mut output) {
+                    Ok(x) => x,
+                    Err(e) => return eprintln!("zstd: {}", e)
+                };
+                result.extend_from_slice(output.as_slice());
+                if remaining == 0 {
+                    break
+                }
+            }
+
+            if !result.is_empty() && addr.send(result.into()).await.is_err() {
+                return
+            }
+        }
+
+        // end the frame so the peer can tell it from a truncated stream. Directions that never carried data stay empty.
+        if !started {
+            return
+        }
+        let mut end = vec![];
+        loop {
+            let mut output = OutBuffer::around(&mut buffer[..]);
+            let remaining = match encoder.finish(&mut output, true) {
+                Ok(x) => x,
+                Err(e) => return eprintln!("zstd: {}", e)
+            };
+            end.extend_from_slice(output.as_slice());
+            if remaining == 0 {
+                break
+            }
+        }
+        let _ = addr.send(end.into()).await;
+    }
+
+    async fn inflate(&self, mut addr: impl api::Address, mut mail: impl api::Mailbox) {
+        let mut decoder = match &self.dictionary {
+            Some(dictionary) => Decoder::with_dictionary(dictionary),
+            None => Decoder::new()
+        }.unwrap();
+
+        let mut buffer = vec![0; 65536];
+        let mut started = false;
+        while let Some(msg) = mail.recv().await {
+            started = true;
+            let mut input = InBuffer::around(&msg);
+
+            loop {
+                let mut output = OutBuffer::around(&mut buffer[..]);
+                let hint = match decoder.run(&mut input, &mut output) {
+                    Ok(x) => x,
+                    Err(_) => return eprintln!("zstd: corrupted data")
+                };
+
+                let full = output.pos() == output.capacity();
+                if output.pos() > 0 && addr.send(output.as_slice().into()).await.is_err() {
+                    return
+                }
+
+                // a zero hint means the frame is complete and fully flushed
+                if hint == 0 {
+                    if input.pos() < msg.len() {
+                        eprintln!("zstd: trailing data after the end of the stream")
+                    }
+                    return
+                }
+
+                // the decoder may hold more data if the output buffer is full
+                if input.pos() == msg.len() && !full {
+                    break
+                }
+            }
+        }
+
+        if started {
+            eprintln!("zstd: stream ended without the end mark, it might be truncated")
+        }
+    }
+}
+
+pub fn init<R: api::Runtime>() -> &'static dyn api::Component<R> {
+    &Component {}
+}
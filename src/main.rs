@@ -50,12 +50,21 @@ fn main() {
         #[cfg(feature = "aead")]
         aead::init(),
 
+        #[cfg(feature = "arq")]
+        arq::init(),
+
         #[cfg(feature = "auth")]
         auth::init(),
 
         #[cfg(feature = "balance")]
         balance::init(),
 
+        #[cfg(feature = "brotli")]
+        brotli::init(),
+
+        #[cfg(feature = "capture")]
+        capture::init(),
+
         #[cfg(feature = "drop")]
         drop::init(),
 
@@ -65,12 +74,36 @@ fn main() {
         #[cfg(feature = "exec")]
         exec::init(),
 
+        #[cfg(feature = "fec")]
+        fec::init(),
+
+        #[cfg(feature = "frame")]
+        frame::init(),
+
         #[cfg(feature = "http2")]
         http2::init(),
 
+        #[cfg(feature = "inspect")]
+        inspect::init(),
+
+        #[cfg(feature = "lz4")]
+        lz4::init(),
+
         #[cfg(feature = "miniz")]
         miniz::init(),
 
+        #[cfg(feature = "mux")]
+        mux::init(),
+
+        #[cfg(feature = "noise")]
+        noise::init(),
+
+        #[cfg(feature = "obfs")]
+        obfs::init(),
+
+        #[cfg(feature = "record")]
+        record::init(),
+
         #[cfg(feature = "socks5")]
         socks5::init(),
 
@@ -94,9 +127,20 @@ fn main() {
 
         #[cfg(feature = "xor")]
         xor::init(),
+
+        #[cfg(feature = "zstd")]
+        zstd::init(),
     ];
 
     let args: Vec<_> = std::env::args().collect();
+
+    #[cfg(feature = "noise")]
+    if args.len() == 2 && args[1] == "--genkey" {
+        let (private, public) = noise::generate_keypair();
+        println!("private key: {}\npublic key: {}", private, public);
+        std::process::exit(0);
+    }
+
     if args.len() != 2 {
         print!("Sopipe {}", option_env!("CARGO_PKG_VERSION").unwrap_or_default());
         for comp in components.iter() {
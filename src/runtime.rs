@@ -40,6 +40,30 @@ impl api::Mailbox for Mailbox {
     }
 }
 
+#[derive(Clone)]
+pub struct StatusReporter(std::sync::Arc<tokio::sync::watch::Sender<Option<api::Status>>>);
+
+impl api::StatusReporter for StatusReporter {
+    fn report(&self, status: api::Status) {
+        self.0.send_if_modified(|x| {
+            if x.is_some() {
+                return false
+            }
+            *x = Some(status);
+            true
+        });
+    }
+}
+
+pub struct StatusWatcher(tokio::sync::watch::Receiver<Option<api::Status>>);
+
+impl api::StatusWatcher for StatusWatcher {
+    #[allow(clippy::type_complexity)]
+    fn wait(&mut self) -> Pin<Box<dyn Future<Output=Option<api::Status>> + Send + '_>> {
+        Box::pin(async { self.0.wait_for(|x| x.is_some()).await.ok().and_then(|x| *x) })
+    }
+}
+
 /// A handler for actors to call the runtime
 pub struct RuntimeHandler {
     runtime: &'static Runtime,
@@ -50,6 +74,8 @@ pub struct RuntimeHandler {
 impl api::Runtime for RuntimeHandler {
     type Address = Address;
     type Mailbox = Mailbox;
+    type StatusReporter = StatusReporter;
+    type StatusWatcher = StatusWatcher;
 
     fn spawn_next(&self, index: usize, metadata: api::MetaData, address: impl Into<Option<Self::Address>>, mailbox: impl Into<Option<Self::Mailbox>>) {
         if self.is_composite {
@@ -86,6 +112,11 @@ impl api::Runtime for RuntimeHandler {
         (Address(tx), Mailbox(rx))
     }
 
+    fn status_channel(&self) -> (Self::StatusReporter, Self::StatusWatcher) {
+        let (tx, rx) = tokio::sync::watch::channel(None);
+        (StatusReporter(tx.into()), StatusWatcher(rx))
+    }
+
     fn spawn_task<F: Future + Send + 'static>(&self, task: F) where F::Output: Send {
         tokio::spawn(async {
             let _c = Counter::new(&self.node.task_count); // use Drop in case of panic
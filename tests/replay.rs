@@ -0,0 +1,23 @@
+use std::process::{Command, Output};
+
+/// replay a recording against `echo` with verification
+fn replay(name: &str, recording: &str) -> Output {
+    let path = std::env::temp_dir().join(format!("sopipe-test-{}-{}.txt", std::process::id(), name));
+    std::fs::write(&path, recording).unwrap();
+    let script = format!("replay({:?}, verify, fast) => echo", path.to_str().unwrap());
+    let output = Command::new(env!("CARGO_BIN_EXE_sopipe")).arg(script).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+#[test]
+fn replay_matched() {
+    let output = replay("matched", "0 0 open\n10 0 > 68656c6c6f\n20 0 < 68656c6c6f\n30 0 > end\n40 0 < end\n");
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn replay_mismatched() {
+    let output = replay("mismatched", "0 0 open\n10 0 > 68656c6c6f\n20 0 < 776f726c64\n30 0 > end\n40 0 < end\n");
+    assert_eq!(output.status.code(), Some(1), "{}", String::from_utf8_lossy(&output.stderr));
+}